@@ -1,5 +1,7 @@
 use std::fs::File;
 use std::io::{Read, Result, Error, ErrorKind};
+use std::mem;
+use cpu::*;
 use mmu::*;
 use tools::*;
 use vm::*;
@@ -122,9 +124,14 @@ pub fn mmu_from_rom_file(filename : String) -> Result<Mmu> {
 
     let mut contents : Vec<u8> = Vec::new();
 
-    let number_of_bytes = try!(file.read_to_end(&mut contents));
+    try!(file.read_to_end(&mut contents));
 
-    match number_of_bytes {
+    mmu_from_rom(contents)
+}
+
+/// Load the content of a .gb file into the Mmu struct
+pub fn mmu_from_rom(contents : Vec<u8>) -> Result<Mmu> {
+    match contents.len() {
         0x8000 => {
             let mmu = Mmu {
                 rom : contents[0x0000..0x4000].to_vec(),
@@ -157,15 +164,111 @@ pub fn describe_cartridge(mmu : &Mmu) -> Result<CartridgeDesc> {
 /// Load a .gb file and wrap it into a Vm struct
 pub fn load_rom(filename : String) -> Result<Vm> {
     let mmu = try!(mmu_from_rom_file(filename));
+    let mut vm : Vm = Default::default();
+    try!(insert_mmu(&mut vm, mmu));
+    Ok(vm)
+}
+
+/// Replace the cartridge of the Vm by the content of a .gb file
+///
+/// Act like ejecting the cartridge and inserting a new one:
+/// the whole hardware (CPU, memory, GPU, timers) goes back to its
+/// power-on state, but the settings of the host (callbacks) are kept.
+///
+/// On error, the Vm is left untouched.
+pub fn insert_cartridge(vm : &mut Vm, contents : Vec<u8>) -> Result<()> {
+    let mmu = try!(mmu_from_rom(contents));
+    insert_mmu(vm, mmu)
+}
+
+/// Replace the cartridge of the Vm by the one of `mmu`
+/// (see insert_cartridge).
+fn insert_mmu(vm : &mut Vm, mmu : Mmu) -> Result<()> {
     let cartridge = try!(describe_cartridge(&mmu));
 
-    Ok(Vm {
-        cpu : Default::default(),
+    // Keep the host settings
+    let scanline_callback = mem::replace(&mut vm.scanline_callback, Hook(None));
+    let audit_callback = mem::replace(&mut vm.audit_callback, Hook(None));
+    let interrupt_callback = mem::replace(&mut vm.interrupt_callback, Hook(None));
+    let frame_callback = mem::replace(&mut vm.frame_callback, Hook(None));
+    let access_log = mem::replace(&mut vm.access_log, Default::default());
+    // Addresses executed by the previous game are forgotten
+    let mut executed_map = mem::replace(&mut vm.executed_map, Vec::new());
+    for word in executed_map.iter_mut() {
+        *word = 0;
+    }
+
+    *vm = Vm {
         mmu : mmu,
-        gpu : Default::default(),
         cartridge : cartridge,
 
         joypad_row_cross : 0x0F,
         joypad_row_buttons : 0x0F,
-    })
+        joypad_lines : 0x0F,
+
+        scanline_callback : scanline_callback,
+        peripherals_frozen : vm.peripherals_frozen,
+        audit_callback : audit_callback,
+        interrupt_callback : interrupt_callback,
+        frame_callback : frame_callback,
+        present_interval : vm.present_interval,
+        access_log : access_log,
+        instruction_budget : vm.instruction_budget,
+        illegal_opcode_behavior : vm.illegal_opcode_behavior,
+        executed_map : executed_map,
+        trace_ring : TraceRing {
+            capacity : vm.trace_ring.capacity,
+            .. Default::default()
+        },
+        // Snapshots of the previous game are dropped
+        rewind : Rewind {
+            capacity : vm.rewind.capacity,
+            interval_frames : vm.rewind.interval_frames,
+            .. Default::default()
+        },
+
+        .. Default::default()
+    };
+    Ok(())
+}
+
+/// Checksums of the cartridge header, declared and computed
+#[derive(PartialEq, Eq, Clone, Copy, Default, Debug)]
+pub struct ChecksumReport {
+    /// Header checksum stored at 0x014D
+    pub header_declared : u8,
+    /// Header checksum of the bytes 0x0134-0x014C
+    pub header_computed : u8,
+    /// Global checksum stored at 0x014E-0x014F
+    pub global_declared : u16,
+    /// Sum of all the bytes of the ROM, except 0x014E-0x014F
+    pub global_computed : u16,
+    /// True if the header checksum is correct.
+    /// The bios refuse to start a cartridge with a wrong header checksum.
+    pub header_ok : bool,
+    /// True if the global checksum is correct (not checked by the hardware)
+    pub global_ok : bool,
+}
+
+/// Compute the checksums of the cartridge, and compare them
+/// with the ones declared in its header.
+pub fn verify_checksums(vm : &Vm) -> ChecksumReport {
+    let rom = &vm.mmu.rom;
+
+    let header = rom[0x134..0x14D].iter()
+        .fold(0u8, |x, byte| x.wrapping_sub(*byte).wrapping_sub(1));
+
+    let global = rom.iter().chain(vm.mmu.srom.iter()).enumerate()
+        .filter(|&(addr, _)| addr != 0x14E && addr != 0x14F)
+        .fold(0u16, |sum, (_, byte)| sum.wrapping_add(*byte as u16));
+
+    let global_declared = w_combine(rom[0x14E], rom[0x14F]);
+    ChecksumReport {
+        header_declared : rom[0x14D],
+        header_computed : header,
+        global_declared : global_declared,
+        global_computed : global,
+        header_ok : rom[0x14D] == header,
+        global_ok : global_declared == global,
+    }
 }
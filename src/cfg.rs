@@ -0,0 +1,129 @@
+/** CFG Module (Control flow graph of a region of code)
+
+Split the code reachable from an address into basic blocks,
+following the jumps, calls and returns. Usefull to understand
+the structure of a ROM in a debugger.
+ */
+
+use std::collections::{BTreeMap, BTreeSet};
+use vm::*;
+use cpu::*;
+
+#[derive(PartialEq, Eq, Clone, Debug)]
+/// A sequence of instructions executed from the first to the last one
+pub struct BasicBlock {
+    /// Address of the first instruction
+    pub start : u16,
+    /// Address following the last instruction (excluded)
+    pub end : u16,
+    /// Start of the blocks that can be executed after this one
+    /// (only the blocks in the analysed region are listed)
+    pub successors : Vec<u16>,
+    /// True if the block end with RET, RETI, or an unconditional jump
+    pub terminal : bool,
+}
+
+#[derive(PartialEq, Eq, Clone, Default, Debug)]
+/// Control flow graph, blocks are sorted by address
+pub struct Cfg {
+    pub blocks : Vec<BasicBlock>,
+}
+
+/// Target of a relative jump of `offset` from the instruction at `addr`
+fn jr_target(addr : u16, offset : i8) -> u16 {
+    addr.wrapping_add(2).wrapping_add(offset as u16)
+}
+
+/// Return the possible next addresses after the instruction `instr`
+/// at `addr`, and true if the instruction always leave the current block.
+///
+/// The second element is true for control flow instructions
+/// (the instruction ends a block).
+fn next_addresses(addr : u16, len : usize, instr : DecodedInstr) -> (Vec<u16>, bool) {
+    let next = addr.wrapping_add(len as u16);
+    match instr {
+        DecodedInstr::Jr { offset } => (vec![jr_target(addr, offset)], true),
+        DecodedInstr::JrCc { offset, .. } => (vec![jr_target(addr, offset), next], true),
+        DecodedInstr::Jp { addr } => (vec![addr], true),
+        DecodedInstr::JpCc { addr, .. } => (vec![addr, next], true),
+        DecodedInstr::Call { addr } => (vec![addr, next], true),
+        DecodedInstr::CallCc { addr, .. } => (vec![addr, next], true),
+        DecodedInstr::Rst { addr } => (vec![addr, next], true),
+        DecodedInstr::RetCc { .. } => (vec![next], true),
+        DecodedInstr::Ret | DecodedInstr::Reti | DecodedInstr::JpHl => (vec![], true),
+        _ => (vec![next], false),
+    }
+}
+
+/// Build the control flow graph of the code reachable from `start`,
+/// without leaving the region `start..end`.
+///
+/// Memory is read without side effects.
+pub fn build_cfg(vm : &Vm, start : u16, end : u16) -> Cfg {
+    let in_range = |addr : u16| addr >= start && addr < end;
+
+    // Decode each reachable instruction
+    let mut instructions : BTreeMap<u16, (DecodedInstr, usize)> = BTreeMap::new();
+    let mut leaders = BTreeSet::new();
+    let mut todo = vec![start];
+    leaders.insert(start);
+
+    while let Some(addr) = todo.pop() {
+        if !in_range(addr) || instructions.contains_key(&addr) {
+            continue;
+        }
+
+        let (instr, len) = decode_at(vm, addr);
+        instructions.insert(addr, (instr, len));
+
+        let (next, is_branch) = next_addresses(addr, len, instr);
+        for target in next {
+            if is_branch {
+                leaders.insert(target);
+            }
+            todo.push(target);
+        }
+    }
+
+    // Split the instructions into blocks
+    let mut cfg : Cfg = Default::default();
+    let mut current : Option<BasicBlock> = None;
+    for (&addr, &(instr, len)) in instructions.iter() {
+        // Close the current block if a new one begin here
+        let contiguous = current.as_ref().map_or(false, |b| b.end == addr);
+        if !contiguous || leaders.contains(&addr) {
+            if let Some(mut block) = current.take() {
+                if contiguous {
+                    block.successors.push(addr);
+                }
+                cfg.blocks.push(block);
+            }
+        }
+
+        let mut block = current.take().unwrap_or(BasicBlock {
+            start : addr,
+            end : addr,
+            successors : Vec::new(),
+            terminal : false,
+        });
+        block.end = addr.wrapping_add(len as u16);
+
+        let (next, is_branch) = next_addresses(addr, len, instr);
+        if is_branch {
+            block.successors = next.into_iter().filter(|a| in_range(*a)).collect();
+            block.terminal = match instr {
+                DecodedInstr::Ret | DecodedInstr::Reti | DecodedInstr::JpHl
+                    | DecodedInstr::Jp { .. } | DecodedInstr::Jr { .. } => true,
+                _ => false,
+            };
+            cfg.blocks.push(block);
+        } else {
+            current = Some(block);
+        }
+    }
+    if let Some(block) = current {
+        cfg.blocks.push(block);
+    }
+
+    cfg
+}
@@ -2,13 +2,18 @@ use vm::*;
 use tools::*;
 use gpu;
 use mmu;
+use io;
 use std::boxed::Box;
+use std::cmp;
+use std::collections::VecDeque;
+use std::ops::{Index, IndexMut};
+use std::str::FromStr;
 
 //////////////////////////////////////////////////////////
 // Registers and utilitary functions to manipulate them
 //////////////////////////////////////////////////////////
 
-#[derive(PartialEq, Eq, Debug)]
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
 pub struct Registers {
         // Registers (a, b, c, d, e, h, l, f) :
         pub rs : [u8 ; 8],
@@ -50,13 +55,39 @@ pub enum Flag {
     C = 4,
 }
 
+#[derive(PartialEq, Eq, Copy, Clone, Default, Debug)]
+/// All the flags of the F register
+pub struct Flags {
+    pub z : bool,
+    pub n : bool,
+    pub h : bool,
+    pub c : bool,
+}
+
+pub fn u8_to_flags(value : u8) -> Flags {
+    Flags {
+        z : (value & 0x80) != 0,
+        n : (value & 0x40) != 0,
+        h : (value & 0x20) != 0,
+        c : (value & 0x10) != 0,
+    }
+}
+
+/// The lower 4 bits are always 0
+pub fn flags_to_u8(flags : Flags) -> u8 {
+    (flags.z as u8) << 7
+        | (flags.n as u8) << 6
+        | (flags.h as u8) << 5
+        | (flags.c as u8) << 4
+}
+
 /// Macro for easy access to registers
 ///
 /// Syntax : `reg![vm; register_name]`
-/// where register_name : Register
+/// where register_name : Register, and vm any CpuBus
 #[macro_export]
 macro_rules! reg {
-    [$vm:expr ; $r:expr] => ($vm.cpu.registers.rs[$r as usize]);
+    [$vm:expr ; $r:expr] => ($vm[$crate::cpu::CPU].registers.rs[$r as usize]);
 }
 
 /// Macro for accessing PC from a vm
@@ -64,7 +95,7 @@ macro_rules! reg {
 /// Syntax : `pc![vm]`
 #[macro_export]
 macro_rules! pc {
-    [$vm:expr] => ($vm.cpu.registers.pc);
+    [$vm:expr] => ($vm[$crate::cpu::CPU].registers.pc);
 }
 
 /// Macro for acessing SP from a vm
@@ -72,7 +103,7 @@ macro_rules! pc {
 /// Syntax : `sp![vm]`
 #[macro_export]
 macro_rules! sp {
-    [$vm:expr] => ($vm.cpu.registers.sp);
+    [$vm:expr] => ($vm[$crate::cpu::CPU].registers.sp);
 }
 
 /// Macro for acessing HL as a u16
@@ -110,12 +141,22 @@ macro_rules! set_hl {
 }
 
 /// Reset the flags of the Vm (set all flags to 0)
-pub fn reset_flags(vm: &mut Vm) {
+pub fn reset_flags<B : CpuBus>(vm : &mut B) {
     reg![vm ; Register::F] = 0
 }
 
+/// Read all the flags at once
+pub fn flags(vm : &Vm) -> Flags {
+    u8_to_flags(reg![vm ; Register::F])
+}
+
+/// Set all the flags at once
+pub fn set_flags<B : CpuBus>(vm : &mut B, flags : Flags) {
+    reg![vm ; Register::F] = flags_to_u8(flags);
+}
+
 /// Set the specified flag to the value given
-pub fn set_flag(vm : &mut Vm, flag : Flag, value : bool) {
+pub fn set_flag<B : CpuBus>(vm : &mut B, flag : Flag, value : bool) {
     if value {
         reg![vm ; Register::F] |= 1 << flag as usize
     }
@@ -125,14 +166,14 @@ pub fn set_flag(vm : &mut Vm, flag : Flag, value : bool) {
 }
 
 /// Get the value from two registers h and l glued together (h:l)
-pub fn get_r16(vm : &mut Vm, h : Register, l : Register) -> u16 {
+pub fn get_r16<B : CpuBus>(vm : &B, h : Register, l : Register) -> u16 {
     let initial_h = reg![vm ; h];
     let initial_l = reg![vm ; l];
     w_combine(initial_h, initial_l)
 }
 
 /// Set the value of two registers h and l glued together (h:l)
-pub fn set_r16(vm : &mut Vm, h : Register, l : Register, value : u16) {
+pub fn set_r16<B : CpuBus>(vm : &mut B, h : Register, l : Register, value : u16) {
     let (value_h, value_l) = w_uncombine(value);
     reg![vm ; h] = value_h;
     reg![vm ; l] = value_l;
@@ -141,6 +182,106 @@ pub fn set_r16(vm : &mut Vm, h : Register, l : Register, value : u16) {
     }
 }
 
+/// Copy of the registers of the CPU (without the rest of the Vm)
+pub fn save_registers(vm : &Vm) -> Registers {
+    vm.cpu.registers
+}
+
+/// Restore registers saved with save_registers.
+///
+/// The lower 4 bits of F are always cleared.
+pub fn restore_registers(vm : &mut Vm, registers : Registers) {
+    vm.cpu.registers = registers;
+    reg![vm ; Register::F] &= 0xF0;
+}
+
+impl FromStr for Register {
+    type Err = String;
+
+    /// Parse the name of a register (case insensitive)
+    fn from_str(name : &str) -> Result<Register, String> {
+        match name.to_uppercase().as_str() {
+            "A" => Ok(Register::A),
+            "B" => Ok(Register::B),
+            "C" => Ok(Register::C),
+            "D" => Ok(Register::D),
+            "E" => Ok(Register::E),
+            "H" => Ok(Register::H),
+            "L" => Ok(Register::L),
+            "F" => Ok(Register::F),
+            _ => Err(format!("Unknown register {}", name)),
+        }
+    }
+}
+
+impl FromStr for Flag {
+    type Err = String;
+
+    /// Parse the name of a flag (case insensitive)
+    fn from_str(name : &str) -> Result<Flag, String> {
+        match name.to_uppercase().as_str() {
+            "Z" => Ok(Flag::Z),
+            "N" => Ok(Flag::N),
+            "H" => Ok(Flag::H),
+            "C" => Ok(Flag::C),
+            _ => Err(format!("Unknown flag {}", name)),
+        }
+    }
+}
+
+/// The two registers of a 16 bits pair (AF, BC, DE or HL)
+fn register_pair(name : &str) -> Option<(Register, Register)> {
+    match name.to_uppercase().as_str() {
+        "AF" => Some((Register::A, Register::F)),
+        "BC" => Some((Register::B, Register::C)),
+        "DE" => Some((Register::D, Register::E)),
+        "HL" => Some((Register::H, Register::L)),
+        _ => None,
+    }
+}
+
+/// Value of the register `name`, a 8 bits register (A, F, B, ...),
+/// a 16 bits pair (AF, BC, DE, HL), SP or PC.
+///
+/// Usefull for debuggers accepting commands like `show HL`.
+pub fn register_by_name(vm : &Vm, name : &str) -> Result<u16, String> {
+    match name.to_uppercase().as_str() {
+        "SP" => return Ok(sp![vm]),
+        "PC" => return Ok(pc![vm]),
+        _ => (),
+    }
+    if let Some((h, l)) = register_pair(name) {
+        return Ok(w_combine(reg![vm ; h], reg![vm ; l]));
+    }
+    let register = try!(Register::from_str(name));
+    Ok(reg![vm ; register] as u16)
+}
+
+/// Set the register `name` (see register_by_name) to `value`.
+///
+/// The lower 4 bits of F are always cleared. The value of a 8 bits
+/// register must fit in a byte.
+pub fn set_register_by_name(vm : &mut Vm, name : &str, value : u16) -> Result<(), String> {
+    match name.to_uppercase().as_str() {
+        "SP" => {sp![vm] = value; return Ok(())},
+        "PC" => {pc![vm] = value; return Ok(())},
+        _ => (),
+    }
+    if let Some((h, l)) = register_pair(name) {
+        set_r16(vm, h, l, value);
+        return Ok(());
+    }
+    let register = try!(Register::from_str(name));
+    if value > 0xFF {
+        return Err(format!("Value 0x{:X} too big for register {}", value, name));
+    }
+    reg![vm ; register] = value as u8;
+    if register == Register::F {
+        reg![vm ; register] &= 0xF0;
+    }
+    Ok(())
+}
+
 //////////////////////////////////////////
 // CPU structurs, data types, and states
 //////////////////////////////////////////
@@ -199,7 +340,7 @@ impl Default for InterruptState {
     fn default() -> InterruptState { InterruptState::IDisabled }
 }
 
-#[derive(PartialEq, Eq, Default, Debug)]
+#[derive(PartialEq, Eq, Clone, Default, Debug)]
 pub struct Cpu {
     /// CPU's registers
     pub registers : Registers,
@@ -211,18 +352,108 @@ pub struct Cpu {
 
     /// Timer implementation
     pub timers : Timers,
+
+    /// Address of the instruction being executed
+    pub instruction_pc : u16,
+
+    /// True after a HALT, until an interrupt is requested
+    pub halted : bool,
+    /// True after a STOP, until a joypad line goes low
+    /// (see update_joypad_lines).
+    pub stopped : bool,
+    /// True after an illegal opcode was executed with
+    /// IllegalBehavior::Hang, or a HALT with IE cleared.
+    /// Nothing can unlock the CPU.
+    pub locked : bool,
+    /// Illegal opcode met with IllegalBehavior::Error by the last
+    /// instruction, not reported yet (see step).
+    pub illegal_opcode : Option<IllegalOpcode>,
+}
+
+/// What to do when an illegal opcode (0xD3, 0xDB, ...) is executed
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum IllegalBehavior {
+    /// Print a warning, and run it as a NOP
+    Ignore,
+    /// Lock the CPU forever, like the real hardware
+    Hang,
+    /// Don't execute it, and report an error (see step)
+    Error,
+}
+
+impl Default for IllegalBehavior {
+    fn default() -> IllegalBehavior { IllegalBehavior::Ignore }
+}
+
+/// An illegal opcode was executed (see IllegalBehavior::Error)
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub struct IllegalOpcode {
+    /// Address of the opcode
+    pub pc : u16,
+    pub opcode : u8,
+}
+
+/// Index of the Cpu in a CpuBus
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub struct CpuIndex;
+
+/// The Cpu of a CpuBus is `bus[CPU]`, mutable or not
+/// (see reg!, pc!, sp!, hl! and flag!).
+pub const CPU : CpuIndex = CpuIndex;
+
+/// A memory bus carrying the state of the CPU
+///
+/// The instructions run over it. The Vm is the usual one, but a
+/// test can run an instruction over a simpler bus, backed by an
+/// array (see MemoryBus).
+pub trait CpuBus : mmu::MemoryBus + Index<CpuIndex, Output = Cpu> + IndexMut<CpuIndex> {
+    /// State of the lines P10-P13 of the joypad (see i_stop),
+    /// no key held by default.
+    fn joypad_lines(&self) -> u8 {
+        0x0F
+    }
+
+    /// What to do on an illegal opcode (see i_invalid),
+    /// ignored by default.
+    fn illegal_opcode_behavior(&self) -> IllegalBehavior {
+        IllegalBehavior::Ignore
+    }
+}
+
+impl Index<CpuIndex> for Vm {
+    type Output = Cpu;
+
+    fn index(&self, _ : CpuIndex) -> &Cpu {
+        &self.cpu
+    }
+}
+
+impl IndexMut<CpuIndex> for Vm {
+    fn index_mut(&mut self, _ : CpuIndex) -> &mut Cpu {
+        &mut self.cpu
+    }
+}
+
+impl CpuBus for Vm {
+    fn joypad_lines(&self) -> u8 {
+        self.joypad_lines
+    }
+
+    fn illegal_opcode_behavior(&self) -> IllegalBehavior {
+        self.illegal_opcode_behavior
+    }
 }
 
 /// Read a byte from the memory pointed by PC, and increment PC
-pub fn read_program_byte(vm : &mut Vm) -> u8 {
-    let byte = mmu::rb(pc![vm], vm);
+pub fn read_program_byte<B : CpuBus>(vm : &mut B) -> u8 {
+    let byte = vm.read(pc![vm]);
     pc![vm] = pc![vm].wrapping_add(1);
     return byte;
 }
 
 /// Read a word (2bytes) from the memory pointed by PC, and increment PC
-pub fn read_program_word(vm : &mut Vm) -> u16 {
-    let word = mmu::rw(pc![vm], vm);
+pub fn read_program_word<B : CpuBus>(vm : &mut B) -> u16 {
+    let word = vm.read_word(pc![vm]);
     pc![vm] = pc![vm].wrapping_add(2);
     return word;
 }
@@ -236,66 +467,186 @@ pub fn update_cpu_clock(clock : Clock, vm : &mut Vm) {
     vm.cpu.clock.t = vm.cpu.clock.t.wrapping_add(clock.t);
 }
 
-/// Update timers with the enlapsed time clock
-pub fn update_timers(clock : Clock, vm : &mut Vm) {
+pub fn tac_to_u8(tac : TimerControl) -> u8 {
+    // The unused bits read as 1
+    0xF8 | (tac.running as u8) << 2 | (tac.timer_mode & 0x03)
+}
+
+pub fn u8_to_tac(value : u8) -> TimerControl {
+    TimerControl {
+        timer_mode : value & 0x03,
+        running    : (0b00000100 & value) != 0,
+    }
+}
+
+/// Number of cycles between two TIMA increments
+pub fn timer_period(tac : TimerControl) -> u64 {
+    match tac.timer_mode {
+        0b00 => 16,
+        0b01 => 1,
+        0b10 => 8,
+        0b11 => 4,
+        _    => {
+            println!("Timer Mode equal to {} where value in [0,3] expected!",
+            tac.timer_mode);
+            16
+        },
+    }
+}
+
+/// Increment TIMA, and request the timer interrupt on overflow
+fn increment_tima(vm : &mut Vm) {
     let t = &mut vm.cpu.timers;
-    let ifr = &mut vm.mmu.ifr;
 
+    // If the counter is about to overflow
+    if t.tima == 0xFF {
+        // Reset timer and set interrupt flag
+        t.tima = t.tma;
+        vm.mmu.ifr.timer = true;
+    } else {
+        // Increment timer
+        t.tima = t.tima.wrapping_add(1);
+    }
+}
+
+/// Update timers with the enlapsed time clock
+pub fn update_timers(clock : Clock, vm : &mut Vm) {
     // Handle DIV timer
-    t.imp_4c += clock.t;
-    while t.imp_4c >= 4 {
-        t.imp_4c -= 4;
-        t.div = t.div.wrapping_add(1);
+    {
+        let t = &mut vm.cpu.timers;
+        t.imp_4c += clock.t;
+        while t.imp_4c >= 4 {
+            t.imp_4c -= 4;
+            t.div = t.div.wrapping_add(1);
+        }
     }
 
     // Handle TIMA timer
-    if t.tac.running {
+    if vm.cpu.timers.tac.running {
         // Check the time step depending on mode
-        let diff = match t.tac.timer_mode {
-            0b00 => 16,
-            0b01 => 1,
-            0b10 => 8,
-            0b11 => 4,
-            _    => {
-                println!("Timer Mode equal to {} where value in [0,3] expected!",
-                t.tac.timer_mode);
-                16
-            },
-        };
+        let diff = timer_period(vm.cpu.timers.tac);
 
-        t.imp_nc += clock.t;
+        vm.cpu.timers.imp_nc += clock.t;
         // Take into account each time step
-        while t.imp_nc >= diff {
-            t.imp_nc -= diff;
-
-            // If the counter is about to overflow
-            if t.tima == 0xFF {
-                // Reset timer and set interrupt flag
-                t.tima = t.tma;
-                ifr.timer = true;
-            } else {
-                // Increment timer
-                t.tima = t.tima.wrapping_add(1);
-            }
+        while vm.cpu.timers.imp_nc >= diff {
+            vm.cpu.timers.imp_nc -= diff;
+            increment_tima(vm);
         }
     }
 }
 
-/// Execute exactly one instruction by the CPU
+/// Write to DIV, resetting the internal counter of the timers
 ///
-/// The function load the byte pointed by PC, increment PC,
-/// and call dispatch with the opcode to run the instruction.
-pub fn execute_one_instruction(vm : &mut Vm) {
-    // Disable bios if needed
-    if pc![vm] >= 0x100 {
-        vm.mmu.bios_enabled = false;
+/// TIMA is incremented on the falling edge of a bit of this counter.
+/// If the bit selected by TAC was set, the reset create a falling
+/// edge and TIMA is incremented once (the "DIV write glitch").
+pub fn write_div(vm : &mut Vm) {
+    let tac = vm.cpu.timers.tac;
+    if tac.running && vm.cpu.timers.imp_nc * 2 >= timer_period(tac) {
+        increment_tima(vm);
     }
 
-    //print!("0x{:04x}:", pc![vm]);
-    //let old_pc = pc![vm];
+    let t = &mut vm.cpu.timers;
+    t.div = 0;
+    t.imp_4c = 0;
+    t.imp_nc = 0;
+}
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+/// An instruction executed by the CPU
+pub struct TraceEntry {
+    /// Address of the instruction
+    pub pc : u16,
+    /// First byte of the instruction (0xCB for the prefixed ones)
+    pub opcode : u8,
+    /// Registers before the execution of the instruction
+    pub registers : Registers,
+}
+
+/// Ring buffer of the last executed instructions
+/// (see enable_trace_ring).
+#[derive(PartialEq, Eq, Default, Debug)]
+pub struct TraceRing {
+    /// Maximum number of instructions kept (0 disable the trace)
+    pub capacity : usize,
+    /// Last instructions, from the oldest to the most recent
+    pub entries : VecDeque<TraceEntry>,
+}
+
+/// Keep the last `capacity` executed instructions (see recent_trace).
+///
+/// A capacity of 0 disable the trace. The previous trace is cleared.
+pub fn enable_trace_ring(vm : &mut Vm, capacity : usize) {
+    vm.trace_ring = TraceRing {
+        capacity : capacity,
+        entries : VecDeque::with_capacity(capacity),
+    };
+}
+
+/// Last executed instructions, from the oldest to the most recent.
+pub fn recent_trace(vm : &Vm) -> Vec<TraceEntry> {
+    vm.trace_ring.entries.iter().cloned().collect()
+}
+
+/// Add an instruction to the trace ring, if enabled
+fn record_trace(vm : &mut Vm, entry : TraceEntry) {
+    let ring = &mut vm.trace_ring;
+    if ring.capacity == 0 {
+        return;
+    }
+    if ring.entries.len() == ring.capacity {
+        ring.entries.pop_front();
+    }
+    ring.entries.push_back(entry);
+}
+
+/// Record the addresses of the executed instructions
+/// (see was_executed). The previous record is cleared.
+pub fn enable_executed_map(vm : &mut Vm) {
+    vm.executed_map = vec![0 ; 0x10000 / 64];
+}
+
+/// Stop recording the executed instructions, and forget them.
+pub fn disable_executed_map(vm : &mut Vm) {
+    vm.executed_map = Vec::new();
+}
 
+/// Bitset of the executed addresses: the bit `addr % 64` of the
+/// word `addr / 64` is set if an instruction starting at `addr` was
+/// executed since enable_executed_map (see was_executed).
+///
+/// The map is empty when disabled.
+pub fn executed_map(vm : &Vm) -> &[u64] {
+    &vm.executed_map
+}
+
+/// True if an instruction starting at `addr` was executed since
+/// enable_executed_map.
+///
+/// Tell the code from the data for a disassembler. Always false
+/// when disabled.
+pub fn was_executed(vm : &Vm, addr : u16) -> bool {
+    match vm.executed_map.get(addr as usize / 64) {
+        Some(word) => word & (1 << (addr % 64)) != 0,
+        None => false,
+    }
+}
+
+/// Fetch, decode and run the instruction pointed by PC
+fn run_next_instruction(vm : &mut Vm) -> Clock {
     // Run the instruction
+    vm.cpu.instruction_pc = pc![vm];
+    let registers = vm.cpu.registers;
     let opcode = read_program_byte(vm);
+    record_trace(vm, TraceEntry {
+        pc : vm.cpu.instruction_pc,
+        opcode : opcode,
+        registers : registers,
+    });
+    if !vm.executed_map.is_empty() {
+        let pc = vm.cpu.instruction_pc;
+        vm.executed_map[pc as usize / 64] |= 1 << (pc % 64);
+    }
     let Instruction(name, fct) = match opcode {
         0xCB => dispatch_cb(read_program_byte(vm)),
         _    => dispatch(opcode),
@@ -313,20 +664,63 @@ pub fn execute_one_instruction(vm : &mut Vm) {
     );*/
 
     // Run opcode
-    let clock = (fct)(vm);
+    (fct)(vm)
+}
 
-    // Update CPU's clock and timers
+/// Execute exactly one instruction by the CPU
+///
+/// The function load the byte pointed by PC, increment PC,
+/// and call dispatch with the opcode to run the instruction.
+pub fn execute_one_instruction(vm : &mut Vm) {
+    //print!("0x{:04x}:", pc![vm]);
+    //let old_pc = pc![vm];
+
+    // Only the last illegal opcode met can be reported
+    vm.cpu.illegal_opcode = None;
+
+    // While halted, nothing is executed but the time still run
+    let clock = if vm.cpu.halted || vm.cpu.stopped || vm.cpu.locked {
+        Clock { m:1, t:4 }
+    } else {
+        run_next_instruction(vm)
+    };
+
+    // An illegal opcode reported as an error wasn't executed:
+    // nothing else happen, PC stay on it (see step)
+    if vm.cpu.illegal_opcode.is_some() {
+        return;
+    }
+
+    // Update CPU's clock and timers (stopped in STOP mode)
     update_cpu_clock(clock, vm);
-    update_timers(clock, vm);
+    if !vm.peripherals_frozen && !vm.cpu.stopped {
+        update_timers(clock, vm);
+    }
 
-    // Handle interupts
-    if vm.cpu.interrupt == InterruptState::IDisableNextInst
-        || vm.cpu.interrupt == InterruptState::IEnabled {
+    // Cycles of the instruction, the wake up and the interrupt dispatch
+    let mut cycles = clock.t;
+
+    // A requested interrupt wake up the CPU, even if IME is disabled
+    if vm.cpu.halted && interrupt_requested(vm) {
+        let clock = wake_up(vm);
+        cycles += clock.t;
+        update_cpu_clock(clock, vm);
+        if !vm.peripherals_frozen {
+            update_timers(clock, vm);
+        }
+    }
+
+    // Handle interupts (a locked CPU ignore them)
+    if !vm.cpu.locked && (vm.cpu.interrupt == InterruptState::IDisableNextInst
+        || vm.cpu.interrupt == InterruptState::IEnabled) {
         let clock = handle_interrupts(vm);
+        cycles += clock.t;
 
         // Update CPU's clock and timers
         update_cpu_clock(clock, vm);
-        update_timers(clock, vm);
+        if !vm.peripherals_frozen {
+            update_timers(clock, vm);
+        }
     }
 
     // Update the interrupt state
@@ -337,36 +731,247 @@ pub fn execute_one_instruction(vm : &mut Vm) {
     };
 
 
-    // Update GPU's mode (Clock, Scanline, VBlank, HBlank, ...)
-    gpu::update_gpu_mode(vm, clock.t);
+    // Update the OAM DMA and GPU's mode (Clock, Scanline, VBlank, HBlank, ...)
+    if !vm.peripherals_frozen {
+        io::update_dma(vm, cycles);
+
+        let in_vblank = vm.gpu.mode == gpu::GpuMode::VerticalBlank;
+        gpu::update_gpu_mode(vm, cycles);
+
+        // A frame was completed
+        if !in_vblank && vm.gpu.mode == gpu::GpuMode::VerticalBlank {
+            rewind_frame_end(vm);
+            present_frame_end(vm);
+        }
+    }
+}
+
+/// Execute one instruction, and report the illegal opcode met
+/// with IllegalBehavior::Error.
+///
+/// PC is left on the illegal opcode, so that the host can fix
+/// the state and resume.
+pub fn step(vm : &mut Vm) -> Result<(), IllegalOpcode> {
+    execute_one_instruction(vm);
+    match vm.cpu.illegal_opcode.take() {
+        Some(illegal) => Err(illegal),
+        None => Ok(()),
+    }
+}
+
+/// True if the CPU is locked by an illegal opcode
+/// (see IllegalBehavior::Hang) or a HALT that can't be woken up.
+pub fn cpu_locked(vm : &Vm) -> bool {
+    vm.cpu.locked
+}
+
+/// Execute exactly `n` instructions and return the number of cycles
+/// consumed.
+///
+/// The interrupts serviced after an instruction are counted as part of
+/// this instruction, so that the count match the trace logs of other
+/// emulators.
+///
+/// Stop early on an illegal opcode met with IllegalBehavior::Error,
+/// left in vm.cpu.illegal_opcode.
+pub fn run_instructions(vm : &mut Vm, n : usize) -> u64 {
+    let start = vm.cpu.clock.t;
+    for _ in 0..n {
+        execute_one_instruction(vm);
+        if vm.cpu.illegal_opcode.is_some() {
+            break;
+        }
+    }
+    vm.cpu.clock.t.wrapping_sub(start)
+}
+
+/// The instruction budget was spent before the end of a run
+/// (see set_instruction_budget).
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub struct BudgetExceeded {
+    /// Number of cycles consumed before stopping
+    pub cycles : u64,
+}
+
+/// Execute instructions until the GPU enters the VBlank mode, and
+/// return the number of cycles consumed.
+///
+/// If the GPU is already in VBlank, run until the next one.
+/// The peripherals must not be frozen, otherwise the GPU never
+/// reach the VBlank.
+///
+/// Stop early with BudgetExceeded if the instruction budget of the
+/// Vm is spent before, and on an illegal opcode met with
+/// IllegalBehavior::Error (left in vm.cpu.illegal_opcode).
+pub fn run_to_vblank(vm : &mut Vm) -> Result<u64, BudgetExceeded> {
+    let start = vm.cpu.clock.t;
+    let mut in_vblank = vm.gpu.mode == gpu::GpuMode::VerticalBlank;
+    let mut count = 0;
+    loop {
+        if vm.instruction_budget.map_or(false, |budget| count >= budget) {
+            return Err(BudgetExceeded { cycles : vm.cpu.clock.t.wrapping_sub(start) });
+        }
+        execute_one_instruction(vm);
+        count += 1;
+        if vm.cpu.illegal_opcode.is_some() {
+            break;
+        }
+
+        let vblank = vm.gpu.mode == gpu::GpuMode::VerticalBlank;
+        if vblank && !in_vblank {
+            break;
+        }
+        in_vblank = vblank;
+    }
+    Ok(vm.cpu.clock.t.wrapping_sub(start))
+}
+
+/// Run `n` frames (see run_to_vblank), and return the last frame
+/// rendered (the rendering memory).
+pub fn run_frames(vm : &mut Vm, n : u64) -> Result<&[u8], BudgetExceeded> {
+    for _ in 0..n {
+        try!(run_to_vblank(vm));
+        if vm.cpu.illegal_opcode.is_some() {
+            break;
+        }
+    }
+    Ok(&vm.gpu.rendering_memory)
+}
+
+/// Error of run_until_serial_pattern
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum RunError {
+    /// The pattern wasn't sent before the timeout
+    Timeout,
+    /// The CPU is locked, the pattern will never be sent
+    Locked,
+    /// An illegal opcode was met (see IllegalBehavior::Error)
+    IllegalOpcode(IllegalOpcode),
+}
+
+/// Execute instructions until `pattern` is sent through the serial
+/// port, or `timeout_cycles` cycles were consumed.
+///
+/// Only the bytes sent after the call are searched, and an empty
+/// pattern is found immediately.
+///
+/// Usefull to run test roms reporting their result through the
+/// serial port ("Passed", the Fibonacci numbers of Mooneye, ...).
+pub fn run_until_serial_pattern(vm : &mut Vm, pattern : &[u8], timeout_cycles : u64) -> Result<(), RunError> {
+    if pattern.is_empty() {
+        return Ok(());
+    }
+    let start = vm.cpu.clock.t;
+    let first = vm.serial_output.len();
+    let mut from = first;
+    loop {
+        if vm.serial_output[from..].windows(pattern.len()).any(|w| w == pattern) {
+            return Ok(());
+        }
+        // Keep the beginning of a pattern not fully sent yet
+        from = cmp::max(first, (vm.serial_output.len() + 1).saturating_sub(pattern.len()));
+
+        if vm.cpu.clock.t.wrapping_sub(start) >= timeout_cycles {
+            return Err(RunError::Timeout);
+        }
+        if cpu_locked(vm) {
+            return Err(RunError::Locked);
+        }
+        try!(step(vm).map_err(RunError::IllegalOpcode));
+    }
+}
+
+/// Request the interrupt `which`, and service it immediately
+/// if it is enabled (IME and IE).
+///
+/// Usefull to test an interrupt handler without running
+/// the timer or the GPU.
+pub fn trigger_interrupt(vm : &mut Vm, which : mmu::Interrupt) {
+    mmu::raise_interrupt(&mut vm.mmu.ifr, which);
+
+    // A locked CPU ignore the interrupts
+    if vm.cpu.locked {
+        return;
+    }
+    // A requested interrupt wake up the CPU, even if IME is disabled
+    let wake = if vm.cpu.halted && interrupt_requested(vm) {
+        wake_up(vm)
+    } else {
+        Clock { m:0, t:0 }
+    };
+
+    if vm.cpu.interrupt == InterruptState::IDisableNextInst
+        || vm.cpu.interrupt == InterruptState::IEnabled {
+        let clock = handle_interrupts(vm);
+        let clock = Clock { m:clock.m, t:wake.t + clock.t };
+
+        // Update CPU's clock and timers
+        update_cpu_clock(clock, vm);
+        if !vm.peripherals_frozen {
+            update_timers(clock, vm);
+        }
+    }
+}
+
+/// Leave the HALT mode
+///
+/// With IME enabled, the CPU spend 4 cycles before the dispatch
+/// of the interrupt.
+fn wake_up(vm : &mut Vm) -> Clock {
+    vm.cpu.halted = false;
+    if vm.cpu.interrupt == InterruptState::IDisableNextInst
+        || vm.cpu.interrupt == InterruptState::IEnabled {
+        Clock { m:0, t:4 }
+    } else {
+        Clock { m:0, t:0 }
+    }
+}
+
+/// Disable the interrupts and jump to the handler of `which`
+///
+/// Push PC like RST, but take 20 cycles instead of 16.
+fn service_interrupt(vm : &mut Vm, which : mmu::Interrupt) -> Clock {
+    let addr = match which {
+        mmu::Interrupt::VBlank  => 0x40,
+        mmu::Interrupt::LcdStat => 0x48,
+        mmu::Interrupt::Timer   => 0x50,
+        mmu::Interrupt::Serial  => 0x58,
+        mmu::Interrupt::Joypad  => 0x60,
+    };
+    let return_addr = pc![vm];
+
+    vm.cpu.interrupt = InterruptState::IDisabled;
+    i_rst(vm, addr);
+    fire_interrupt_callback(vm, which, return_addr);
+    Clock { m:0, t:20 }
+}
+
+/// True if an enabled interrupt is requested (IE & IF not null)
+pub fn interrupt_requested(vm : &Vm) -> bool {
+    mmu::interrupt_to_u8(vm.mmu.ier) & mmu::interrupt_to_u8(vm.mmu.ifr) & 0x1F != 0
 }
 
 pub fn handle_interrupts(vm : &mut Vm) -> Clock {
     // Handle vblank
     if vm.mmu.ier.vblank && vm.mmu.ifr.vblank {
         vm.mmu.ifr.vblank = false;
-        vm.cpu.interrupt = InterruptState::IDisabled;
-        return i_rst(vm, 0x40);
+        return service_interrupt(vm, mmu::Interrupt::VBlank);
     }
     if vm.mmu.ier.lcd_stat && vm.mmu.ifr.lcd_stat {
         vm.mmu.ifr.lcd_stat = false;
-        vm.cpu.interrupt = InterruptState::IDisabled;
-        return i_rst(vm, 0x48);
+        return service_interrupt(vm, mmu::Interrupt::LcdStat);
     }
     if vm.mmu.ier.timer && vm.mmu.ifr.timer {
         vm.mmu.ifr.timer = false;
-        vm.cpu.interrupt = InterruptState::IDisabled;
-        return i_rst(vm, 0x50);
+        return service_interrupt(vm, mmu::Interrupt::Timer);
     }
     if vm.mmu.ier.serial && vm.mmu.ifr.serial {
         vm.mmu.ifr.serial = false;
-        vm.cpu.interrupt = InterruptState::IDisabled;
-        return i_rst(vm, 0x58);
+        return service_interrupt(vm, mmu::Interrupt::Serial);
     }
     if vm.mmu.ier.joypad && vm.mmu.ifr.joypad {
         vm.mmu.ifr.joypad = false;
-        vm.cpu.interrupt = InterruptState::IDisabled;
-        return i_rst(vm, 0x60);
+        return service_interrupt(vm, mmu::Interrupt::Joypad);
     }
     return Clock { m:0, t:0 };
 }
@@ -398,8 +1003,7 @@ pub fn dispatch(opcode : u8) -> Instruction {
         0x0E => mk_inst![vm> "LDCd8",   i_ldrd8(vm, Register::C)],
         0x0F => mk_inst![vm> "RRCA",    i_rrca(vm)],
 
-        //0x10 => STOP
-        0x10 => mk_inst![vm> "STOP",    i_nop(vm)],
+        0x10 => mk_inst![vm> "STOP",    i_stop(vm)],
         0x11 => mk_inst![vm> "LDDEd16", i_ldr16d16(vm, Register::D, Register::E)],
         0x12 => mk_inst![vm> "LDDEmA",  i_ldr16mr(vm, Register::D, Register::E, Register::A)],
         0x13 => mk_inst![vm> "INCDE",   i_incr16(vm, Register::D, Register::E)],
@@ -507,7 +1111,7 @@ pub fn dispatch(opcode : u8) -> Instruction {
         0x73 => mk_inst![vm> "LDHLmE",  i_ldr16mr(vm, Register::H, Register::L, Register::E)],
         0x74 => mk_inst![vm> "LDHLmH",  i_ldr16mr(vm, Register::H, Register::L, Register::H)],
         0x75 => mk_inst![vm> "LDHLmL",  i_ldr16mr(vm, Register::H, Register::L, Register::L)],
-        0x76 => mk_inst![vm> "HALT",    Default::default()],
+        0x76 => mk_inst![vm> "HALT",    i_halt(vm)],
         0x77 => mk_inst![vm> "LDHLmA",  i_ldr16mr(vm, Register::H, Register::L, Register::A)],
         0x78 => mk_inst![vm> "LDAB",    i_ldrr(vm, Register::A, Register::B)],
         0x79 => mk_inst![vm> "LDAC",    i_ldrr(vm, Register::A, Register::C)],
@@ -805,14 +1409,14 @@ pub fn dispatch_cb(opcode : u8) -> Instruction {
         0x85 => mk_inst![vm> "RES0L",    i_res(vm, 0, Register::L)],
         0x86 => mk_inst![vm> "RES0HLm",  i_reshlm(vm, 0)],
         0x87 => mk_inst![vm> "RES0A",    i_res(vm, 0, Register::A)],
-        0x88 => mk_inst![vm> "RES0B",    i_res(vm, 1, Register::B)],
-        0x89 => mk_inst![vm> "RES0C",    i_res(vm, 1, Register::C)],
-        0x8A => mk_inst![vm> "RES0D",    i_res(vm, 1, Register::D)],
-        0x8B => mk_inst![vm> "RES0E",    i_res(vm, 1, Register::E)],
-        0x8C => mk_inst![vm> "RES0H",    i_res(vm, 1, Register::H)],
-        0x8D => mk_inst![vm> "RES0L",    i_res(vm, 1, Register::L)],
-        0x8E => mk_inst![vm> "RES0HLm",  i_reshlm(vm, 1)],
-        0x8F => mk_inst![vm> "RES0A",    i_res(vm, 1, Register::A)],
+        0x88 => mk_inst![vm> "RES1B",    i_res(vm, 1, Register::B)],
+        0x89 => mk_inst![vm> "RES1C",    i_res(vm, 1, Register::C)],
+        0x8A => mk_inst![vm> "RES1D",    i_res(vm, 1, Register::D)],
+        0x8B => mk_inst![vm> "RES1E",    i_res(vm, 1, Register::E)],
+        0x8C => mk_inst![vm> "RES1H",    i_res(vm, 1, Register::H)],
+        0x8D => mk_inst![vm> "RES1L",    i_res(vm, 1, Register::L)],
+        0x8E => mk_inst![vm> "RES1HLm",  i_reshlm(vm, 1)],
+        0x8F => mk_inst![vm> "RES1A",    i_res(vm, 1, Register::A)],
 
         0x90 => mk_inst![vm> "RES2B",    i_res(vm, 2, Register::B)],
         0x91 => mk_inst![vm> "RES2C",    i_res(vm, 2, Register::C)],
@@ -873,14 +1477,14 @@ pub fn dispatch_cb(opcode : u8) -> Instruction {
         0xC5 => mk_inst![vm> "SET0L",    i_set(vm, 0, Register::L)],
         0xC6 => mk_inst![vm> "SET0HLm",  i_sethlm(vm, 0)],
         0xC7 => mk_inst![vm> "SET0A",    i_set(vm, 0, Register::A)],
-        0xC8 => mk_inst![vm> "SET0B",    i_set(vm, 1, Register::B)],
-        0xC9 => mk_inst![vm> "SET0C",    i_set(vm, 1, Register::C)],
-        0xCA => mk_inst![vm> "SET0D",    i_set(vm, 1, Register::D)],
-        0xCB => mk_inst![vm> "SET0E",    i_set(vm, 1, Register::E)],
-        0xCC => mk_inst![vm> "SET0H",    i_set(vm, 1, Register::H)],
-        0xCD => mk_inst![vm> "SET0L",    i_set(vm, 1, Register::L)],
-        0xCE => mk_inst![vm> "SET0HLm",  i_sethlm(vm, 1)],
-        0xCF => mk_inst![vm> "SET0A",    i_set(vm, 1, Register::A)],
+        0xC8 => mk_inst![vm> "SET1B",    i_set(vm, 1, Register::B)],
+        0xC9 => mk_inst![vm> "SET1C",    i_set(vm, 1, Register::C)],
+        0xCA => mk_inst![vm> "SET1D",    i_set(vm, 1, Register::D)],
+        0xCB => mk_inst![vm> "SET1E",    i_set(vm, 1, Register::E)],
+        0xCC => mk_inst![vm> "SET1H",    i_set(vm, 1, Register::H)],
+        0xCD => mk_inst![vm> "SET1L",    i_set(vm, 1, Register::L)],
+        0xCE => mk_inst![vm> "SET1HLm",  i_sethlm(vm, 1)],
+        0xCF => mk_inst![vm> "SET1A",    i_set(vm, 1, Register::A)],
 
         0xD0 => mk_inst![vm> "SET2B",    i_set(vm, 2, Register::B)],
         0xD1 => mk_inst![vm> "SET2C",    i_set(vm, 2, Register::C)],
@@ -944,7 +1548,7 @@ pub fn dispatch_cb(opcode : u8) -> Instruction {
 /////////////////////////////////////////
 
 /// No Operation
-pub fn i_nop(_ : &mut Vm) -> Clock {
+pub fn i_nop<B : CpuBus>(_ : &mut B) -> Clock {
     Clock { m:1, t:4 }
 }
 
@@ -953,7 +1557,7 @@ pub fn i_nop(_ : &mut Vm) -> Clock {
 /// Syntax : `LD vm:Vm dst:Register src:Register`
 ///
 /// > LD Register <- Register
-pub fn i_ldrr(vm : &mut Vm, dst : Register, src : Register) -> Clock {
+pub fn i_ldrr<B : CpuBus>(vm : &mut B, dst : Register, src : Register) -> Clock {
     reg![vm; dst] = reg![vm; src];
     Clock { m:1, t:4 }
 }
@@ -963,9 +1567,9 @@ pub fn i_ldrr(vm : &mut Vm, dst : Register, src : Register) -> Clock {
 /// Syntax : `LDrr16m vm:Vm h:Register l:Register`
 ///
 /// > LDrr16m Register <- (h:l)
-pub fn i_ldrr16m(vm : &mut Vm, dst : Register, h : Register, l : Register) -> Clock {
+pub fn i_ldrr16m<B : CpuBus>(vm : &mut B, dst : Register, h : Register, l : Register) -> Clock {
     let addr = get_r16(vm, h, l);
-    reg![vm ; dst] = mmu::rb(addr, vm);
+    reg![vm ; dst] = vm.read(addr);
     Clock { m:1, t:8 }
 }
 
@@ -974,9 +1578,9 @@ pub fn i_ldrr16m(vm : &mut Vm, dst : Register, h : Register, l : Register) -> Cl
 /// Syntax : `LDr16mr vm:Vm h:Register l:Register`
 ///
 /// > LDr16mr (h:l) <- Register
-pub fn i_ldr16mr(vm : &mut Vm, h : Register, l : Register, src : Register) -> Clock {
+pub fn i_ldr16mr<B : CpuBus>(vm : &mut B, h : Register, l : Register, src : Register) -> Clock {
     let addr = get_r16(vm, h, l);
-    mmu::wb(addr, reg![vm ; src], vm);
+    vm.write(addr, reg![vm ; src]);
     Clock { m:1, t:8 }
 }
 
@@ -985,9 +1589,9 @@ pub fn i_ldr16mr(vm : &mut Vm, h : Register, l : Register, src : Register) -> Cl
 /// Syntax : `LDCmA vm:Vm`
 ///
 /// > LDCmA (0xFF00 + C) <- A
-pub fn i_ldcma(vm : &mut Vm) -> Clock {
+pub fn i_ldcma<B : CpuBus>(vm : &mut B) -> Clock {
     let addr = 0xFF00 + reg![vm ; Register::C] as u16;
-    mmu::wb(addr, reg![vm ; Register::A], vm);
+    vm.write(addr, reg![vm ; Register::A]);
     Clock { m:1, t:8 }
 }
 
@@ -996,9 +1600,9 @@ pub fn i_ldcma(vm : &mut Vm) -> Clock {
 /// Syntax : `LDACm vm:Vm`
 ///
 /// > LDACm A <- (0xFF00 + C)
-pub fn i_ldacm(vm : &mut Vm) -> Clock {
+pub fn i_ldacm<B : CpuBus>(vm : &mut B) -> Clock {
     let addr = 0xFF00 + reg![vm ; Register::C] as u16;
-    reg![vm ; Register::A] = mmu::rb(addr, vm);
+    reg![vm ; Register::A] = vm.read(addr);
     Clock { m:1, t:8 }
 }
 
@@ -1008,9 +1612,9 @@ pub fn i_ldacm(vm : &mut Vm) -> Clock {
 /// Syntax : `LDHa8mA vm:Vm`
 ///
 /// > LDH (0xFF00 + a8) <- A
-pub fn i_ldha8ma(vm : &mut Vm) -> Clock {
+pub fn i_ldha8ma<B : CpuBus>(vm : &mut B) -> Clock {
     let addr = 0xFF00 + read_program_byte(vm) as u16;
-    mmu::wb(addr, reg![vm ; Register::A], vm);
+    vm.write(addr, reg![vm ; Register::A]);
     Clock { m:2, t:12 }
 }
 
@@ -1019,15 +1623,15 @@ pub fn i_ldha8ma(vm : &mut Vm) -> Clock {
 /// Syntax : `LDHAa8m vm:Vm`
 ///
 /// > LDH A <- (0xFF00 + a8)
-pub fn i_ldhaa8m(vm : &mut Vm) -> Clock {
+pub fn i_ldhaa8m<B : CpuBus>(vm : &mut B) -> Clock {
     let addr = 0xFF00 + read_program_byte(vm) as u16;
-    reg![vm ; Register::A] = mmu::rb(addr, vm);
+    reg![vm ; Register::A] = vm.read(addr);
     Clock { m:2, t:12 }
 }
 
 /// Implementation for LD[I|D] (HL) A
-pub fn i_ldmod_hlma(vm : &mut Vm, modificator : i16) -> Clock {
-    mmu::wb(hl![vm], reg![vm ; Register::A], vm);
+pub fn i_ldmod_hlma<B : CpuBus>(vm : &mut B, modificator : i16) -> Clock {
+    vm.write(hl![vm], reg![vm ; Register::A]);
 
     let sum = hl![vm].wrapping_add(modificator as u16);
     set_hl!(vm, sum as u16);
@@ -1035,8 +1639,8 @@ pub fn i_ldmod_hlma(vm : &mut Vm, modificator : i16) -> Clock {
 }
 
 /// Implementation for LD[I|D] A (HL)
-pub fn i_ldmod_ahlm(vm : &mut Vm, modificator : i16) -> Clock {
-    reg![vm ; Register::A] = mmu::rb(hl![vm], vm);
+pub fn i_ldmod_ahlm<B : CpuBus>(vm : &mut B, modificator : i16) -> Clock {
+    reg![vm ; Register::A] = vm.read(hl![vm]);
 
     let sum = hl![vm].wrapping_add(modificator as u16);
     set_hl!(vm, sum);
@@ -1046,58 +1650,59 @@ pub fn i_ldmod_ahlm(vm : &mut Vm, modificator : i16) -> Clock {
 /// Load the value of A in (HL) and increment HL
 ///
 /// > LDI (HL+) <- A
-pub fn i_ldihlma(vm : &mut Vm) -> Clock {i_ldmod_hlma(vm, 1)}
+pub fn i_ldihlma<B : CpuBus>(vm : &mut B) -> Clock {i_ldmod_hlma(vm, 1)}
 
 /// Load the value of (HL) in A and increment HL
 ///
 /// > LDI A <- (HL+)
-pub fn i_ldiahlm(vm : &mut Vm) -> Clock {i_ldmod_ahlm(vm, 1)}
+pub fn i_ldiahlm<B : CpuBus>(vm : &mut B) -> Clock {i_ldmod_ahlm(vm, 1)}
 
 /// Load the value of A in (HL) and decrement HL
 ///
 /// > LDD (HL-) <- A
-pub fn i_lddhlma(vm : &mut Vm) -> Clock {i_ldmod_hlma(vm, -1)}
+pub fn i_lddhlma<B : CpuBus>(vm : &mut B) -> Clock {i_ldmod_hlma(vm, -1)}
 
 /// Load the value of (HL) in A and decrement HL
 ///
 /// > LDD A <- (HL-)
-pub fn i_lddahlm(vm : &mut Vm) -> Clock {i_ldmod_ahlm(vm, -1)}
+pub fn i_lddahlm<B : CpuBus>(vm : &mut B) -> Clock {i_ldmod_ahlm(vm, -1)}
 
 /// LD Register <- immediate Word8
-pub fn i_ldrd8(vm : &mut Vm, dst : Register) -> Clock {
+pub fn i_ldrd8<B : CpuBus>(vm : &mut B, dst : Register) -> Clock {
     reg![vm ; dst] = read_program_byte(vm);
     Clock { m:2, t:8 }
 }
 
 /// LD (HL) <- immediate Word8
-pub fn i_ldhlmd8(vm : &mut Vm) -> Clock {
-    mmu::wb(hl![vm], read_program_byte(vm), vm);
+pub fn i_ldhlmd8<B : CpuBus>(vm : &mut B) -> Clock {
+    let value = read_program_byte(vm);
+    vm.write(hl![vm], value);
     Clock { m:2, t:8 }
 }
 
 /// LD (a16) <- a where a16 means the next Word16 as an address
-pub fn i_lda16ma(vm : &mut Vm) -> Clock {
+pub fn i_lda16ma<B : CpuBus>(vm : &mut B) -> Clock {
     let a16 = read_program_word(vm);
-    mmu::wb(a16, reg![vm ; Register::A], vm);
+    vm.write(a16, reg![vm ; Register::A]);
     Clock { m:3, t:12 }
 }
 
 /// LD a <- (a16) where a16 means the next Word16 as an address
-pub fn i_ldaa16m(vm : &mut Vm) -> Clock {
+pub fn i_ldaa16m<B : CpuBus>(vm : &mut B) -> Clock {
     let a16 = read_program_word(vm);
-    reg![vm ; Register::A] = mmu::rb(a16, vm);
+    reg![vm ; Register::A] = vm.read(a16);
     Clock { m:3, t:12 }
 }
 
 /// LD (a16) <- SP where a16 means the next Word16 as an address
-pub fn i_lda16msp(vm : &mut Vm) -> Clock {
+pub fn i_lda16msp<B : CpuBus>(vm : &mut B) -> Clock {
     let a16 = read_program_word(vm);
-    mmu::ww(a16, sp![vm], vm);
+    vm.write_word(a16, sp![vm]);
     Clock { m:3, t:20 }
 }
 
 /// LD r16 <- d16 where d16 means direct Word8 value
-pub fn i_ldr16d16(vm : &mut Vm, h : Register, l : Register) -> Clock {
+pub fn i_ldr16d16<B : CpuBus>(vm : &mut B, h : Register, l : Register) -> Clock {
     let d16 = read_program_word(vm);
     set_r16(vm, h, l, d16);
     Clock { m:3, t:12 }
@@ -1105,20 +1710,20 @@ pub fn i_ldr16d16(vm : &mut Vm, h : Register, l : Register) -> Clock {
 
 
 /// LD SP <- d16 where d16 means direct Word8 value
-pub fn i_ldspd16(vm : &mut Vm) -> Clock {
+pub fn i_ldspd16<B : CpuBus>(vm : &mut B) -> Clock {
     let d16 = read_program_word(vm);
     sp![vm] = d16;
     Clock { m:3, t:12 }
 }
 
 /// LD SP <- HL
-pub fn i_ldsphl(vm : &mut Vm) -> Clock {
+pub fn i_ldsphl<B : CpuBus>(vm : &mut B) -> Clock {
     sp![vm] = hl![vm];
     Clock { m:1, t:8 }
 }
 
 /// Implement xoring the register A with the value src_val
-pub fn i_xor_imp(src_val : u8, vm : &mut Vm) {
+pub fn i_xor_imp<B : CpuBus>(src_val : u8, vm : &mut B) {
     reg![vm ; Register::A] ^= src_val;
     let result = reg![vm ; Register::A];
     set_flag(vm, Flag::Z, result == 0);
@@ -1126,31 +1731,31 @@ pub fn i_xor_imp(src_val : u8, vm : &mut Vm) {
 
 /// XOR the register A with a register src into A
 /// Syntax : `XOR src:Register`
-pub fn i_xorr(vm : &mut Vm, src : Register) -> Clock {
+pub fn i_xorr<B : CpuBus>(vm : &mut B, src : Register) -> Clock {
     reset_flags(vm);
     i_xor_imp(reg![vm ; src], vm);
-    Clock { m:1, t:8 }
+    Clock { m:1, t:4 }
 }
 
 /// XOR the register A with (HL) into A
 /// Syntax : `XORHLm`
-pub fn i_xorhlm(vm : &mut Vm) -> Clock {
+pub fn i_xorhlm<B : CpuBus>(vm : &mut B) -> Clock {
     reset_flags(vm);
-    i_xor_imp(mmu::rb(hl![vm], vm), vm);
+    i_xor_imp(vm.read(hl![vm]), vm);
     Clock { m:1, t:8 }
 }
 
 /// XOR the register A with immediate word8 into A
 /// Syntax : `XORd8`
-pub fn i_xord8(vm : &mut Vm) -> Clock {
+pub fn i_xord8<B : CpuBus>(vm : &mut B) -> Clock {
     reset_flags(vm);
     let d8 = read_program_byte(vm);
     i_xor_imp(d8, vm);
-    Clock { m:1, t:8 }
+    Clock { m:2, t:8 }
 }
 
 /// Implement swap
-pub fn i_swap_imp(value : u8, vm : &mut Vm) -> u8{
+pub fn i_swap_imp<B : CpuBus>(value : u8, vm : &mut B) -> u8{
     let result = value << 4 | value >> 4;
     reset_flags(vm);
     set_flag(vm, Flag::Z, result == 0);
@@ -1159,21 +1764,21 @@ pub fn i_swap_imp(value : u8, vm : &mut Vm) -> u8{
 
 /// Swap the bits 0-4 and 5-7 of the register `reg`
 /// Syntax : `SWAP src:Register`
-pub fn i_swap(vm : &mut Vm, src : Register) -> Clock {
+pub fn i_swap<B : CpuBus>(vm : &mut B, src : Register) -> Clock {
     reg![vm ; src] = i_swap_imp(reg![vm ; src], vm);
     Clock { m:2, t:8 }
 }
 
 /// Swap the bits 0-4 and 5-7 of (HL)
 /// Syntax : `SWAPHLm`
-pub fn i_swaphlm(vm : &mut Vm) -> Clock {
-    let result = i_swap_imp(mmu::rb(hl![vm], vm), vm);
-    mmu::wb(hl![vm], result, vm);
+pub fn i_swaphlm<B : CpuBus>(vm : &mut B) -> Clock {
+    let result = i_swap_imp(vm.read(hl![vm]), vm);
+    vm.write(hl![vm], result);
     Clock { m:2, t:16 }
 }
 
 /// Implementation of OR of a value with the register A, stored into A
-pub fn i_or_imp(src_val : u8, vm : &mut Vm) {
+pub fn i_or_imp<B : CpuBus>(src_val : u8, vm : &mut B) {
     reset_flags(vm);
     reg![vm ; Register::A] |= src_val;
     let result = reg![vm ; Register::A];
@@ -1183,28 +1788,28 @@ pub fn i_or_imp(src_val : u8, vm : &mut Vm) {
 
 /// Bitwise OR the register A with a register src into A
 /// Syntax : `OR src`
-pub fn i_orr(vm : &mut Vm, src : Register) -> Clock {
+pub fn i_orr<B : CpuBus>(vm : &mut B, src : Register) -> Clock {
     i_or_imp(reg![vm ; src], vm);
     Clock { m:1, t:4 }
 }
 
 /// Bitwise OR the register A with (HL) into A
 /// Syntax : `ORHLm`
-pub fn i_orhlm(vm : &mut Vm) -> Clock {
-    i_or_imp(mmu::rb(hl![vm], vm), vm);
+pub fn i_orhlm<B : CpuBus>(vm : &mut B) -> Clock {
+    i_or_imp(vm.read(hl![vm]), vm);
     Clock { m:1, t:8 }
 }
 
 /// Bitwise OR the register A with the immediate word8 into A
 /// Syntax : `ORd8`
-pub fn i_ord8(vm : &mut Vm) -> Clock {
+pub fn i_ord8<B : CpuBus>(vm : &mut B) -> Clock {
     let byte = read_program_byte(vm);
     i_or_imp(byte, vm);
     Clock { m:2, t:8 }
 }
 
 /// Implementation of AND of a value with the register A, stored into A
-pub fn i_and_imp(src_val : u8, vm : &mut Vm) {
+pub fn i_and_imp<B : CpuBus>(src_val : u8, vm : &mut B) {
     reset_flags(vm);
     reg![vm ; Register::A] &= src_val;
     let result = reg![vm ; Register::A];
@@ -1215,28 +1820,28 @@ pub fn i_and_imp(src_val : u8, vm : &mut Vm) {
 
 /// Bitwise AND the register A with a register src into A
 /// Syntax : `AND src`
-pub fn i_andr(vm : &mut Vm, src : Register) -> Clock {
+pub fn i_andr<B : CpuBus>(vm : &mut B, src : Register) -> Clock {
     i_and_imp(reg![vm ; src], vm);
     Clock { m:1, t:4 }
 }
 
 /// Bitwise AND the register A with (HL) into A
 /// Syntax : `ANDHLm`
-pub fn i_andhlm(vm : &mut Vm) -> Clock {
-    i_and_imp(mmu::rb(hl![vm], vm), vm);
+pub fn i_andhlm<B : CpuBus>(vm : &mut B) -> Clock {
+    i_and_imp(vm.read(hl![vm]), vm);
     Clock { m:1, t:8 }
 }
 
 /// Bitwise AND the register A with the immediate word8 into A
 /// Syntax : `ANDd8`
-pub fn i_andd8(vm : &mut Vm) -> Clock {
+pub fn i_andd8<B : CpuBus>(vm : &mut B) -> Clock {
     let byte = read_program_byte(vm);
     i_and_imp(byte, vm);
     Clock { m:2, t:8 }
 }
 
 /// Implementation of the increment instruction (setting flags)
-pub fn i_inc_impl(vm : &mut Vm, initial_val : u8, final_val : u8) {
+pub fn i_inc_impl<B : CpuBus>(vm : &mut B, initial_val : u8, final_val : u8) {
     set_flag(vm, Flag::Z, final_val == 0);
     set_flag(vm, Flag::H, (initial_val & 0x0F) + 1 > 0x0F);
     set_flag(vm, Flag::N, false);
@@ -1246,7 +1851,7 @@ pub fn i_inc_impl(vm : &mut Vm, initial_val : u8, final_val : u8) {
 /// Always set N to 0.
 ///
 /// Syntax : `INC reg:Register`
-pub fn i_incr(vm : &mut Vm, reg : Register) -> Clock {
+pub fn i_incr<B : CpuBus>(vm : &mut B, reg : Register) -> Clock {
     let initial_val = reg![vm ; reg];
     reg![vm ; reg] = reg![vm ; reg].wrapping_add(1);
     let final_val = reg![vm ; reg];
@@ -1258,10 +1863,10 @@ pub fn i_incr(vm : &mut Vm, reg : Register) -> Clock {
 /// Always set N to 0.
 ///
 /// Syntax : `INCHLm`
-pub fn i_inchlm(vm : &mut Vm) -> Clock {
-    let initial_val = mmu::rb(hl![vm], vm);
+pub fn i_inchlm<B : CpuBus>(vm : &mut B) -> Clock {
+    let initial_val = vm.read(hl![vm]);
     let final_val = initial_val.wrapping_add(1);
-    mmu::wb(hl![vm], final_val, vm);
+    vm.write(hl![vm], final_val);
     i_inc_impl(vm, initial_val, final_val);
     Clock { m:1, t:12 }
 }
@@ -1270,7 +1875,7 @@ pub fn i_inchlm(vm : &mut Vm) -> Clock {
 /// Leave flags unaffected.
 ///
 /// Syntax : `INC hight:Register low:Register`
-pub fn i_incr16(vm : &mut Vm, h : Register, l : Register) -> Clock {
+pub fn i_incr16<B : CpuBus>(vm : &mut B, h : Register, l : Register) -> Clock {
     let initial_val = get_r16(vm, h, l);
     let final_val = initial_val.wrapping_add(1);
     set_r16(vm, h, l, final_val);
@@ -1282,14 +1887,14 @@ pub fn i_incr16(vm : &mut Vm, h : Register, l : Register) -> Clock {
 /// Leave flags unaffected.
 ///
 /// Syntax : `INCSP`
-pub fn i_incsp(vm : &mut Vm) -> Clock {
+pub fn i_incsp<B : CpuBus>(vm : &mut B) -> Clock {
     sp![vm] = sp![vm].wrapping_add(1);
 
     Clock { m:1, t:8 }
 }
 
 /// Implementation of the increment instruction (setting flags)
-pub fn i_dec_impl(vm : &mut Vm, initial_val : u8, final_val : u8) {
+pub fn i_dec_impl<B : CpuBus>(vm : &mut B, initial_val : u8, final_val : u8) {
     set_flag(vm, Flag::Z, final_val == 0);
     set_flag(vm, Flag::H, initial_val & 0x0F == 0);
     set_flag(vm, Flag::N, true);
@@ -1299,7 +1904,7 @@ pub fn i_dec_impl(vm : &mut Vm, initial_val : u8, final_val : u8) {
 /// Always set N to 0.
 ///
 /// Syntax : `DEC reg:Register`
-pub fn i_decr(vm : &mut Vm, reg : Register) -> Clock {
+pub fn i_decr<B : CpuBus>(vm : &mut B, reg : Register) -> Clock {
     let initial_val = reg![vm ; reg];
     let final_val = initial_val.wrapping_sub(1);
     reg![vm ; reg] = final_val;
@@ -1311,10 +1916,10 @@ pub fn i_decr(vm : &mut Vm, reg : Register) -> Clock {
 /// Always set N to 0.
 ///
 /// Syntax : `INCHLm`
-pub fn i_dechlm(vm : &mut Vm) -> Clock {
-    let initial_val = mmu::rb(hl![vm], vm);
+pub fn i_dechlm<B : CpuBus>(vm : &mut B) -> Clock {
+    let initial_val = vm.read(hl![vm]);
     let final_val = initial_val.wrapping_sub(1);
-    mmu::wb(hl![vm], final_val, vm);
+    vm.write(hl![vm], final_val);
     i_dec_impl(vm, initial_val, final_val);
     Clock { m:1, t:12 }
 }
@@ -1323,7 +1928,7 @@ pub fn i_dechlm(vm : &mut Vm) -> Clock {
 /// Leave flags unaffected.
 ///
 /// Syntax : `DEC hight:Register low:Register`
-pub fn i_decr16(vm : &mut Vm, h : Register, l : Register) -> Clock {
+pub fn i_decr16<B : CpuBus>(vm : &mut B, h : Register, l : Register) -> Clock {
     let initial_val = get_r16(vm, h, l);
     let final_val = initial_val.wrapping_sub(1);
     set_r16(vm, h, l, final_val);
@@ -1335,7 +1940,7 @@ pub fn i_decr16(vm : &mut Vm, h : Register, l : Register) -> Clock {
 /// Leave flags unaffected.
 ///
 /// Syntax : `DECSP`
-pub fn i_decsp(vm : &mut Vm) -> Clock {
+pub fn i_decsp<B : CpuBus>(vm : &mut B) -> Clock {
     sp![vm] = sp![vm].wrapping_sub(1);
 
     Clock { m:1, t:8 }
@@ -1345,7 +1950,7 @@ pub fn i_decsp(vm : &mut Vm) -> Clock {
 /// Set register N to 1.
 ///
 /// Syntax : `CP src:Register`
-pub fn i_cpr(vm : &mut Vm, src : Register) -> Clock {
+pub fn i_cpr<B : CpuBus>(vm : &mut B, src : Register) -> Clock {
     let input = reg![vm ; src];
 
     // Update flags and discard result
@@ -1358,8 +1963,8 @@ pub fn i_cpr(vm : &mut Vm, src : Register) -> Clock {
 /// Set register N to 1.
 ///
 /// Syntax : `CPHLm`
-pub fn i_cphlm(vm : &mut Vm) -> Clock {
-    let input = mmu::rb(hl![vm], vm);
+pub fn i_cphlm<B : CpuBus>(vm : &mut B) -> Clock {
+    let input = vm.read(hl![vm]);
 
     // Update flags and discard result
     i_sub_imp(vm, input);
@@ -1371,7 +1976,7 @@ pub fn i_cphlm(vm : &mut Vm) -> Clock {
 /// Set register N to 1.
 ///
 /// Syntax : `CPd8`
-pub fn i_cpd8(vm : &mut Vm) -> Clock {
+pub fn i_cpd8<B : CpuBus>(vm : &mut B) -> Clock {
     let input = read_program_byte(vm);
 
     // Update flags and discard result
@@ -1381,7 +1986,7 @@ pub fn i_cpd8(vm : &mut Vm) -> Clock {
 }
 
 /// Implement substracting value:u8 to the register A and set the flags
-pub fn i_sub_imp(vm : &mut Vm, value : u8) -> u8 {
+pub fn i_sub_imp<B : CpuBus>(vm : &mut B, value : u8) -> u8 {
     let a = reg![vm ; Register::A];
     let b = value;
     let diff = a.wrapping_sub(b);
@@ -1397,7 +2002,7 @@ pub fn i_sub_imp(vm : &mut Vm, value : u8) -> u8 {
 /// Set register N to 1.
 ///
 /// Syntax : `SUB src:Register`
-pub fn i_subr(vm : &mut Vm, src : Register) -> Clock {
+pub fn i_subr<B : CpuBus>(vm : &mut B, src : Register) -> Clock {
     let input = reg![vm ; src];
 
     reg![vm ; Register::A] = i_sub_imp(vm, input);
@@ -1409,8 +2014,8 @@ pub fn i_subr(vm : &mut Vm, src : Register) -> Clock {
 /// Set register N to 1.
 ///
 /// Syntax : `SUBHLm`
-pub fn i_subhlm(vm : &mut Vm) -> Clock {
-    let input = mmu::rb(hl![vm], vm);
+pub fn i_subhlm<B : CpuBus>(vm : &mut B) -> Clock {
+    let input = vm.read(hl![vm]);
 
     reg![vm ; Register::A] = i_sub_imp(vm, input);
 
@@ -1421,7 +2026,7 @@ pub fn i_subhlm(vm : &mut Vm) -> Clock {
 /// Set register N to 1.
 ///
 /// Syntax : `SUBd8`
-pub fn i_subd8(vm : &mut Vm) -> Clock {
+pub fn i_subd8<B : CpuBus>(vm : &mut B) -> Clock {
     let input = read_program_byte(vm);
 
     reg![vm ; Register::A] = i_sub_imp(vm, input);
@@ -1430,7 +2035,7 @@ pub fn i_subd8(vm : &mut Vm) -> Clock {
 }
 
 /// Implement substracting value:u8 and carry to the register A and set the flags
-pub fn i_sbc_imp(vm : &mut Vm, value : u8) -> u8 {
+pub fn i_sbc_imp<B : CpuBus>(vm : &mut B, value : u8) -> u8 {
     let carry = flag![vm ; Flag::C] as u8;
     let a = reg![vm ; Register::A];
     let b = value;
@@ -1447,7 +2052,7 @@ pub fn i_sbc_imp(vm : &mut Vm, value : u8) -> u8 {
 /// Set register N to 1.
 ///
 /// Syntax : `SBC src:Register`
-pub fn i_sbcr(vm : &mut Vm, src : Register) -> Clock {
+pub fn i_sbcr<B : CpuBus>(vm : &mut B, src : Register) -> Clock {
     let input = reg![vm ; src];
 
     reg![vm ; Register::A] = i_sbc_imp(vm, input);
@@ -1459,8 +2064,8 @@ pub fn i_sbcr(vm : &mut Vm, src : Register) -> Clock {
 /// Set register N to 1.
 ///
 /// Syntax : `SBCHLm`
-pub fn i_sbchlm(vm : &mut Vm) -> Clock {
-    let input = mmu::rb(hl![vm], vm);
+pub fn i_sbchlm<B : CpuBus>(vm : &mut B) -> Clock {
+    let input = vm.read(hl![vm]);
 
     reg![vm ; Register::A] = i_sbc_imp(vm, input);
 
@@ -1471,7 +2076,7 @@ pub fn i_sbchlm(vm : &mut Vm) -> Clock {
 /// Set register N to 1.
 ///
 /// Syntax : `SBCd8`
-pub fn i_sbcd8(vm : &mut Vm) -> Clock {
+pub fn i_sbcd8<B : CpuBus>(vm : &mut B) -> Clock {
     let input = read_program_byte(vm);
 
     reg![vm ; Register::A] = i_sbc_imp(vm, input);
@@ -1480,7 +2085,7 @@ pub fn i_sbcd8(vm : &mut Vm) -> Clock {
 }
 
 /// Implement adding value:u8 to the register A and set the flags
-pub fn i_add_imp(vm : &mut Vm, value : u8) -> u8 {
+pub fn i_add_imp<B : CpuBus>(vm : &mut B, value : u8) -> u8 {
     let a = reg![vm ; Register::A];
     let b = value;
     let sum = a.wrapping_add(b);
@@ -1496,7 +2101,7 @@ pub fn i_add_imp(vm : &mut Vm, value : u8) -> u8 {
 /// Set register N to 1.
 ///
 /// Syntax : `ADD src:Register`
-pub fn i_addr(vm : &mut Vm, src : Register) -> Clock {
+pub fn i_addr<B : CpuBus>(vm : &mut B, src : Register) -> Clock {
     let input = reg![vm ; src];
 
     reg![vm ; Register::A] = i_add_imp(vm, input);
@@ -1508,8 +2113,8 @@ pub fn i_addr(vm : &mut Vm, src : Register) -> Clock {
 /// Set register N to 1.
 ///
 /// Syntax : `ADDHLm`
-pub fn i_addhlm(vm : &mut Vm) -> Clock {
-    let input = mmu::rb(hl![vm], vm);
+pub fn i_addhlm<B : CpuBus>(vm : &mut B) -> Clock {
+    let input = vm.read(hl![vm]);
 
     reg![vm ; Register::A] = i_add_imp(vm, input);
 
@@ -1520,7 +2125,7 @@ pub fn i_addhlm(vm : &mut Vm) -> Clock {
 /// Set register N to 1.
 ///
 /// Syntax : `CPd8`
-pub fn i_addd8(vm : &mut Vm) -> Clock {
+pub fn i_addd8<B : CpuBus>(vm : &mut B) -> Clock {
     let input = read_program_byte(vm);
 
     reg![vm ; Register::A] = i_add_imp(vm, input);
@@ -1531,7 +2136,7 @@ pub fn i_addd8(vm : &mut Vm) -> Clock {
 /// Implement 16bits ADD
 ///
 /// Set Z H C.
-pub fn i_add_imp16(vm : &mut Vm, a: u16, b : u16) -> u16 {
+pub fn i_add_imp16<B : CpuBus>(vm : &mut B, a: u16, b : u16) -> u16 {
     let sum = a.wrapping_add(b);
 
     set_flag(vm, Flag::N, false);
@@ -1544,7 +2149,7 @@ pub fn i_add_imp16(vm : &mut Vm, a: u16, b : u16) -> u16 {
 /// Add a r16 register to HL
 ///
 /// Affect only flags H, N and C.
-pub fn i_addhlr16(vm : &mut Vm, h : Register, l : Register) -> Clock {
+pub fn i_addhlr16<B : CpuBus>(vm : &mut B, h : Register, l : Register) -> Clock {
     let a = hl![vm];
     let b = get_r16(vm, h, l);
 
@@ -1557,7 +2162,7 @@ pub fn i_addhlr16(vm : &mut Vm, h : Register, l : Register) -> Clock {
 /// Add SP to HL
 ///
 /// Affect only flags H, N and C.
-pub fn i_addhlsp(vm : &mut Vm) -> Clock {
+pub fn i_addhlsp<B : CpuBus>(vm : &mut B) -> Clock {
     let a = hl![vm];
     let b = sp![vm];
 
@@ -1570,7 +2175,7 @@ pub fn i_addhlsp(vm : &mut Vm) -> Clock {
 /// Add direct Word8 to SP
 ///
 /// Affect all flags.
-pub fn i_addspr8(vm : &mut Vm) -> Clock {
+pub fn i_addspr8<B : CpuBus>(vm : &mut B) -> Clock {
     let a = sp![vm] as u16;
     let b = (read_program_byte(vm) as i8) as u16;
 
@@ -1581,11 +2186,11 @@ pub fn i_addspr8(vm : &mut Vm) -> Clock {
     set_flag(vm, Flag::C, (a & 0xFF) + (b & 0xFF) > 0xFF);
     sp![vm] = sum;
 
-    Clock { m:1, t:8 }
+    Clock { m:2, t:16 }
 }
 
 /// Load in HL the value of SP plus direct Word8
-pub fn i_ldhlspr8(vm : &mut Vm) -> Clock {
+pub fn i_ldhlspr8<B : CpuBus>(vm : &mut B) -> Clock {
     let a = sp![vm];
     let b = (read_program_byte(vm) as i8) as u16;
 
@@ -1601,7 +2206,7 @@ pub fn i_ldhlspr8(vm : &mut Vm) -> Clock {
 
 
 /// Implement adding value:u8 + carry to the register A and set the flags
-pub fn i_adc_imp(vm : &mut Vm, value : u8) -> u8 {
+pub fn i_adc_imp<B : CpuBus>(vm : &mut B, value : u8) -> u8 {
     let carry = flag![vm ; Flag::C] as u8;
     let a = reg![vm ; Register::A];
     let b = value;
@@ -1618,7 +2223,7 @@ pub fn i_adc_imp(vm : &mut Vm, value : u8) -> u8 {
 /// Set register N to 1.
 ///
 /// Syntax : `ADC src:Register`
-pub fn i_adcr(vm : &mut Vm, src : Register) -> Clock {
+pub fn i_adcr<B : CpuBus>(vm : &mut B, src : Register) -> Clock {
     let input = reg![vm ; src];
 
     reg![vm ; Register::A] = i_adc_imp(vm, input);
@@ -1630,8 +2235,8 @@ pub fn i_adcr(vm : &mut Vm, src : Register) -> Clock {
 /// Set register N to 1.
 ///
 /// Syntax : `ADCHLm`
-pub fn i_adchlm(vm : &mut Vm) -> Clock {
-    let input = mmu::rb(hl![vm], vm);
+pub fn i_adchlm<B : CpuBus>(vm : &mut B) -> Clock {
+    let input = vm.read(hl![vm]);
 
     reg![vm ; Register::A] = i_adc_imp(vm, input);
 
@@ -1642,7 +2247,7 @@ pub fn i_adchlm(vm : &mut Vm) -> Clock {
 /// Set register N to 1.
 ///
 /// Syntax : `CPd8`
-pub fn i_adcd8(vm : &mut Vm) -> Clock {
+pub fn i_adcd8<B : CpuBus>(vm : &mut B) -> Clock {
     let input = read_program_byte(vm);
 
     reg![vm ; Register::A] = i_adc_imp(vm, input);
@@ -1653,7 +2258,7 @@ pub fn i_adcd8(vm : &mut Vm) -> Clock {
 /// Test the bit bit from src.
 ///
 /// Affect flags Z,N and H.
-pub fn i_bitr(vm : &mut Vm, bit : usize, src : Register) -> Clock {
+pub fn i_bitr<B : CpuBus>(vm : &mut B, bit : usize, src : Register) -> Clock {
     let bit_value = reg![vm ; src] >> bit & 0x01;
 
     set_flag(vm, Flag::Z, bit_value == 0);
@@ -1666,21 +2271,21 @@ pub fn i_bitr(vm : &mut Vm, bit : usize, src : Register) -> Clock {
 /// Test the bit bit from (HL).
 ///
 /// Affect flags Z,N and H.
-pub fn i_bithlm(vm : &mut Vm, bit : usize) -> Clock {
-    let value = mmu::rb(hl![vm], vm);
+pub fn i_bithlm<B : CpuBus>(vm : &mut B, bit : usize) -> Clock {
+    let value = vm.read(hl![vm]);
     let bit_value = value >> bit & 0x01;
 
     set_flag(vm, Flag::Z, bit_value == 0);
     set_flag(vm, Flag::N, false);
     set_flag(vm, Flag::H, true);
 
-    Clock { m:2, t:16 }
+    Clock { m:2, t:12 }
 }
 
 /// Jump of the length given in direct Word8
 ///
 /// Syntax : `JR`
-pub fn i_jr(vm : &mut Vm) -> Clock {
+pub fn i_jr<B : CpuBus>(vm : &mut B) -> Clock {
     let byte = read_program_byte(vm);
     if byte <= 0x7F {
         pc![vm] = pc![vm].wrapping_add(byte as u16)
@@ -1694,7 +2299,7 @@ pub fn i_jr(vm : &mut Vm) -> Clock {
 /// Jump of the length given in direct Word8 if flag:Flag is set
 ///
 /// Syntax : `JRf flag:Flag`
-pub fn i_jrf(vm : &mut Vm, flag : Flag) -> Clock {
+pub fn i_jrf<B : CpuBus>(vm : &mut B, flag : Flag) -> Clock {
     if flag![vm ; flag] {
         i_jr(vm);
         Clock { m:2, t:12 }
@@ -1708,7 +2313,7 @@ pub fn i_jrf(vm : &mut Vm, flag : Flag) -> Clock {
 /// Jump of the length given in direct Word8 if flag:Flag is not set
 ///
 /// Syntax : `JRnf flag:Flag`
-pub fn i_jrnf(vm : &mut Vm, flag : Flag) -> Clock {
+pub fn i_jrnf<B : CpuBus>(vm : &mut B, flag : Flag) -> Clock {
     if flag![vm ; flag] {
         read_program_byte(vm);
         Clock { m:2, t:8 }
@@ -1722,23 +2327,23 @@ pub fn i_jrnf(vm : &mut Vm, flag : Flag) -> Clock {
 /// Read the next two bytes and jump to the address
 ///
 /// Syntax : `JP`
-pub fn i_jp(vm : &mut Vm) -> Clock {
+pub fn i_jp<B : CpuBus>(vm : &mut B) -> Clock {
     pc![vm] = read_program_word(vm);
     Clock { m:3, t:16 }
 }
 
-/// Read the next two bytes and jump to the address
+/// Jump to the address contained in HL (no memory access)
 ///
 /// Syntax : `JPHL`
-pub fn i_jphl(vm : &mut Vm) -> Clock {
+pub fn i_jphl<B : CpuBus>(vm : &mut B) -> Clock {
     pc![vm] = hl![vm];
-    Clock { m:3, t:16 }
+    Clock { m:1, t:4 }
 }
 
 /// Jump of the address given in direct Word16 if flag:Flag is set
 ///
 /// Syntax : `JPf flag:Flag`
-pub fn i_jpf(vm : &mut Vm, flag : Flag) -> Clock {
+pub fn i_jpf<B : CpuBus>(vm : &mut B, flag : Flag) -> Clock {
     if flag![vm ; flag] {
         i_jp(vm);
         Clock { m:3, t:16 }
@@ -1752,7 +2357,7 @@ pub fn i_jpf(vm : &mut Vm, flag : Flag) -> Clock {
 /// Jump of the address given in direct Word16 if flag:Flag is set
 ///
 /// Syntax : `JPnf flag:Flag`
-pub fn i_jpnf(vm : &mut Vm, flag : Flag) -> Clock {
+pub fn i_jpnf<B : CpuBus>(vm : &mut B, flag : Flag) -> Clock {
     if flag![vm ; flag] {
         read_program_word(vm);
         Clock { m:3, t:12 }
@@ -1767,33 +2372,33 @@ pub fn i_jpnf(vm : &mut Vm, flag : Flag) -> Clock {
 ///
 /// Do note affect any register.
 /// Syntax : `PUSH h:Register l:Register`
-pub fn i_push(vm : &mut Vm, h : Register, l : Register) -> Clock {
+pub fn i_push<B : CpuBus>(vm : &mut B, h : Register, l : Register) -> Clock {
     sp![vm] = sp![vm].wrapping_sub(2);
-    mmu::ww(sp![vm], get_r16(vm, h, l), vm);
+    vm.write_word(sp![vm], get_r16(vm, h, l));
     Clock { m:1, t:16 }
 }
 
 /// Pop a r16 from the stack
 ///
 /// Do note affect any register.
-/// Syntax : `PUSH h:Register l:Register`
-pub fn i_pop(vm : &mut Vm, h : Register, l : Register) -> Clock {
-    let value = mmu::rw(sp![vm], vm);
+/// Syntax : `POP h:Register l:Register`
+pub fn i_pop<B : CpuBus>(vm : &mut B, h : Register, l : Register) -> Clock {
+    let value = vm.read_word(sp![vm]);
     set_r16(vm, h, l, value);
     sp![vm] = sp![vm].wrapping_add(2);
-    Clock { m:1, t:16 }
+    Clock { m:1, t:12 }
 }
 
 /// Call a function at addr a16
 ///
 /// Actualy push PC on the stack and load a16 into PC
 /// Syntax : `CALL`
-pub fn i_call(vm : &mut Vm) -> Clock {
+pub fn i_call<B : CpuBus>(vm : &mut B) -> Clock {
     let a16 = read_program_word(vm);
 
     // Push PC on the stack
     sp![vm] = sp![vm].wrapping_sub(2);
-    mmu::ww(sp![vm], pc![vm], vm);
+    vm.write_word(sp![vm], pc![vm]);
 
     // Update PC
     pc![vm] = a16;
@@ -1804,7 +2409,7 @@ pub fn i_call(vm : &mut Vm) -> Clock {
 ///
 /// Actualy push PC on the stack and load a16 into PC
 /// Syntax : `CALL flag:Flag`
-pub fn i_callf(vm : &mut Vm, flag : Flag) -> Clock {
+pub fn i_callf<B : CpuBus>(vm : &mut B, flag : Flag) -> Clock {
     if flag![vm ; flag] {
         i_call(vm);
         Clock { m:3, t:24 }
@@ -1819,7 +2424,7 @@ pub fn i_callf(vm : &mut Vm, flag : Flag) -> Clock {
 ///
 /// Actualy push PC on the stack and load a16 into PC
 /// Syntax : `CALL flag:Flag`
-pub fn i_callnf(vm : &mut Vm, flag : Flag) -> Clock {
+pub fn i_callnf<B : CpuBus>(vm : &mut B, flag : Flag) -> Clock {
     if flag![vm ; flag] {
         read_program_word(vm);
         Clock { m:3, t:12 }
@@ -1834,9 +2439,9 @@ pub fn i_callnf(vm : &mut Vm, flag : Flag) -> Clock {
 ///
 /// Actualy pop PC from the stack
 /// Syntax : `RET`
-pub fn i_ret(vm : &mut Vm) -> Clock {//TODO
+pub fn i_ret<B : CpuBus>(vm : &mut B) -> Clock {//TODO
     // Pop PC from the stack
-    pc![vm] = mmu::rw(sp![vm], vm);
+    pc![vm] = vm.read_word(sp![vm]);
     sp![vm] = sp![vm].wrapping_add(2);
 
     Clock { m:1, t:16 }
@@ -1846,8 +2451,8 @@ pub fn i_ret(vm : &mut Vm) -> Clock {//TODO
 ///
 /// Actualy pop PC from the stack
 /// Syntax : `RETI`
-pub fn i_reti(vm : &mut Vm) -> Clock {
-    vm.cpu.interrupt = InterruptState::IEnabled;
+pub fn i_reti<B : CpuBus>(vm : &mut B) -> Clock {
+    vm[CPU].interrupt = InterruptState::IEnabled;
     i_ret(vm)
 }
 
@@ -1855,7 +2460,7 @@ pub fn i_reti(vm : &mut Vm) -> Clock {
 ///
 /// Actualy pop PC from the stack
 /// Syntax : `RET flag:Flag`
-pub fn i_retf(vm : &mut Vm, flag : Flag) -> Clock {
+pub fn i_retf<B : CpuBus>(vm : &mut B, flag : Flag) -> Clock {
     if flag![vm ; flag] {
         i_ret(vm);
         Clock { m:1, t:20 }
@@ -1869,7 +2474,7 @@ pub fn i_retf(vm : &mut Vm, flag : Flag) -> Clock {
 ///
 /// Actualy pop PC from the stack
 /// Syntax : `RET flag:Flag`
-pub fn i_retnf(vm : &mut Vm, flag : Flag) -> Clock {
+pub fn i_retnf<B : CpuBus>(vm : &mut B, flag : Flag) -> Clock {
     if flag![vm ; flag] {
         Clock { m:1, t:8 }
     }
@@ -1880,7 +2485,7 @@ pub fn i_retnf(vm : &mut Vm, flag : Flag) -> Clock {
 }
 
 /// Implementation of RL
-pub fn i_rl_imp(value : u8, vm : &mut Vm) -> u8 {
+pub fn i_rl_imp<B : CpuBus>(value : u8, vm : &mut B) -> u8 {
     let carry = flag![vm ; Flag::C] as u8;
     let result = (value << 1) | carry;
 
@@ -1897,7 +2502,7 @@ pub fn i_rl_imp(value : u8, vm : &mut Vm) -> u8 {
 /// Bit 7 goes in carry, and carry goes at reg's 0 bit.
 ///
 /// Syntax : `RL reg:Register`
-pub fn i_rl(vm : &mut Vm, reg : Register) -> Clock {
+pub fn i_rl<B : CpuBus>(vm : &mut B, reg : Register) -> Clock {
     reg![vm ; reg] = i_rl_imp(reg![vm ; reg], vm);
     Clock { m:2, t:8 }
 }
@@ -1908,10 +2513,10 @@ pub fn i_rl(vm : &mut Vm, reg : Register) -> Clock {
 /// Bit 7 goes in carry, and carry goes at reg's 0 bit.
 ///
 /// Syntax : `RLA`
-pub fn i_rla(vm : &mut Vm) -> Clock {
+pub fn i_rla<B : CpuBus>(vm : &mut B) -> Clock {
     i_rl(vm, Register::A);
     set_flag(vm, Flag::Z, false);
-    Clock { m:2, t:8 }
+    Clock { m:1, t:4 }
 }
 
 /// Rotate Left through carry
@@ -1920,18 +2525,18 @@ pub fn i_rla(vm : &mut Vm) -> Clock {
 /// Bit 7 goes in carry, and carry goes at (HL)'s 0 bit.
 ///
 /// Syntax : `RLHLm`
-pub fn i_rlhlm(vm : &mut Vm) -> Clock {
+pub fn i_rlhlm<B : CpuBus>(vm : &mut B) -> Clock {
     // Read value
-    let value = mmu::rb(hl![vm], vm);
+    let value = vm.read(hl![vm]);
     let result = i_rl_imp(value, vm);
     // Write value
-    mmu::wb(hl![vm], result, vm);
+    vm.write(hl![vm], result);
 
     Clock { m:2, t:16 }
 }
 
 /// Implementation of RR
-pub fn i_rr_imp(value : u8, vm : &mut Vm) -> u8 {
+pub fn i_rr_imp<B : CpuBus>(value : u8, vm : &mut B) -> u8 {
     let carry = flag![vm ; Flag::C] as u8;
     let result = (value >> 1) | carry << 7;
 
@@ -1948,7 +2553,7 @@ pub fn i_rr_imp(value : u8, vm : &mut Vm) -> u8 {
 /// Bit 0 goes in carry, and carry goes at reg's 7 bit.
 ///
 /// Syntax : `RR reg:Register`
-pub fn i_rr(vm : &mut Vm, reg : Register) -> Clock {
+pub fn i_rr<B : CpuBus>(vm : &mut B, reg : Register) -> Clock {
     reg![vm ; reg] = i_rr_imp(reg![vm ; reg], vm);
     Clock { m:2, t:8 }
 }
@@ -1962,7 +2567,7 @@ pub fn i_rr(vm : &mut Vm, reg : Register) -> Clock {
 /// Reset Z flag.
 ///
 /// Syntax : `RR reg:Register`
-pub fn i_rra(vm : &mut Vm) -> Clock {
+pub fn i_rra<B : CpuBus>(vm : &mut B) -> Clock {
     i_rr(vm, Register::A);
     set_flag(vm, Flag::Z, false);
     Clock { m:1, t:4 }
@@ -1975,18 +2580,18 @@ pub fn i_rra(vm : &mut Vm) -> Clock {
 /// Bit 0 goes in carry, and carry goes at (HL)'s 7 bit.
 ///
 /// Syntax : `RRHLm`
-pub fn i_rrhlm(vm : &mut Vm) -> Clock {
+pub fn i_rrhlm<B : CpuBus>(vm : &mut B) -> Clock {
     // Read value
-    let value = mmu::rb(hl![vm], vm);
+    let value = vm.read(hl![vm]);
     let result = i_rr_imp(value, vm);
     // Write value
-    mmu::wb(hl![vm], result, vm);
+    vm.write(hl![vm], result);
 
     Clock { m:2, t:16 }
 }
 
 /// Implementation of SLA
-pub fn i_sla_imp(value : u8, vm : &mut Vm) -> u8 {
+pub fn i_sla_imp<B : CpuBus>(value : u8, vm : &mut B) -> u8 {
     let result = value << 1;
 
     reset_flags(vm);
@@ -2002,7 +2607,7 @@ pub fn i_sla_imp(value : u8, vm : &mut Vm) -> u8 {
 /// Bit 7 goes in carry, and register's bit 0 is set to 0.
 ///
 /// Syntax : `SLA reg:Register`
-pub fn i_sla(vm : &mut Vm, reg : Register) -> Clock {
+pub fn i_sla<B : CpuBus>(vm : &mut B, reg : Register) -> Clock {
     reg![vm ; reg] = i_sla_imp(reg![vm ; reg], vm);
     Clock { m:2, t:8 }
 }
@@ -2013,18 +2618,18 @@ pub fn i_sla(vm : &mut Vm, reg : Register) -> Clock {
 /// Bit 7 goes in carry, and (HL)'s bit 0 is set to 0.
 ///
 /// Syntax : `SLAHLm`
-pub fn i_slahlm(vm : &mut Vm) -> Clock {
+pub fn i_slahlm<B : CpuBus>(vm : &mut B) -> Clock {
     // Read value
-    let value = mmu::rb(hl![vm], vm);
+    let value = vm.read(hl![vm]);
     let result = i_sla_imp(value, vm);
     // Write value
-    mmu::wb(hl![vm], result, vm);
+    vm.write(hl![vm], result);
 
     Clock { m:2, t:16 }
 }
 
 /// Implementation of SRA
-pub fn i_sra_imp(value : u8, vm : &mut Vm) -> u8 {
+pub fn i_sra_imp<B : CpuBus>(value : u8, vm : &mut B) -> u8 {
     let result = value >> 1 | value & 0x80;
 
     reset_flags(vm);
@@ -2040,7 +2645,7 @@ pub fn i_sra_imp(value : u8, vm : &mut Vm) -> u8 {
 /// Bit 7 stay inchanged, and register's bit 0 goes in carry.
 ///
 /// Syntax : `SRA reg:Register`
-pub fn i_sra(vm : &mut Vm, reg : Register) -> Clock {
+pub fn i_sra<B : CpuBus>(vm : &mut B, reg : Register) -> Clock {
     reg![vm ; reg] = i_sra_imp(reg![vm ; reg], vm);
     Clock { m:2, t:8 }
 }
@@ -2051,18 +2656,18 @@ pub fn i_sra(vm : &mut Vm, reg : Register) -> Clock {
 /// Bit 7 stay inchanged, and register's bit 0 goes in carry.
 ///
 /// Syntax : `SRAHLm`
-pub fn i_srahlm(vm : &mut Vm) -> Clock {
+pub fn i_srahlm<B : CpuBus>(vm : &mut B) -> Clock {
     // Read value
-    let value = mmu::rb(hl![vm], vm);
+    let value = vm.read(hl![vm]);
     let result = i_sra_imp(value, vm);
     // Write value
-    mmu::wb(hl![vm], result, vm);
+    vm.write(hl![vm], result);
 
     Clock { m:2, t:16 }
 }
 
 /// Implementation of SRL
-pub fn i_srl_imp(value : u8, vm : &mut Vm) -> u8 {
+pub fn i_srl_imp<B : CpuBus>(value : u8, vm : &mut B) -> u8 {
     let result = value >> 1;
 
     reset_flags(vm);
@@ -2078,7 +2683,7 @@ pub fn i_srl_imp(value : u8, vm : &mut Vm) -> u8 {
 /// Bit 7 is set to 0, and register's bit 0 goes in carry.
 ///
 /// Syntax : `SRL reg:Register`
-pub fn i_srl(vm : &mut Vm, reg : Register) -> Clock {
+pub fn i_srl<B : CpuBus>(vm : &mut B, reg : Register) -> Clock {
     reg![vm ; reg] = i_srl_imp(reg![vm ; reg], vm);
     Clock { m:2, t:8 }
 }
@@ -2089,18 +2694,18 @@ pub fn i_srl(vm : &mut Vm, reg : Register) -> Clock {
 /// Bit 7 is set to 0, and register's bit 0 goes in carry.
 ///
 /// Syntax : `SRLHLm`
-pub fn i_srlhlm(vm : &mut Vm) -> Clock {
+pub fn i_srlhlm<B : CpuBus>(vm : &mut B) -> Clock {
     // Read value
-    let value = mmu::rb(hl![vm], vm);
+    let value = vm.read(hl![vm]);
     let result = i_srl_imp(value, vm);
     // Write value
-    mmu::wb(hl![vm], result, vm);
+    vm.write(hl![vm], result);
 
     Clock { m:2, t:16 }
 }
 
 /// Implementation of RLC
-pub fn i_rlc_imp(value : u8, vm : &mut Vm) -> u8 {
+pub fn i_rlc_imp<B : CpuBus>(value : u8, vm : &mut B) -> u8 {
     let result = (value << 1) | (value >> 7);
 
     reset_flags(vm);
@@ -2122,7 +2727,7 @@ pub fn i_rlc_imp(value : u8, vm : &mut Vm) -> u8 {
 /// Bit 7 goes in carry.
 ///
 /// Syntax : `RLC reg:Register`
-pub fn i_rlc(vm : &mut Vm, reg : Register) -> Clock {
+pub fn i_rlc<B : CpuBus>(vm : &mut B, reg : Register) -> Clock {
     reg![vm ; reg] = i_rlc_imp(reg![vm ; reg], vm);
     Clock { m:2, t:8 }
 }
@@ -2135,7 +2740,7 @@ pub fn i_rlc(vm : &mut Vm, reg : Register) -> Clock {
 /// Always reset Z flag
 ///
 /// Syntax : `RLCA`
-pub fn i_rlca(vm : &mut Vm) -> Clock {
+pub fn i_rlca<B : CpuBus>(vm : &mut B) -> Clock {
     i_rlc(vm, Register::A);
     set_flag(vm, Flag::Z, false);
     Clock { m:1, t:4 }
@@ -2147,18 +2752,18 @@ pub fn i_rlca(vm : &mut Vm) -> Clock {
 /// Bit 7 goes in carry.
 ///
 /// Syntax : `RLCHLm`
-pub fn i_rlchlm(vm : &mut Vm) -> Clock {
+pub fn i_rlchlm<B : CpuBus>(vm : &mut B) -> Clock {
     // Read value
-    let value = mmu::rb(hl![vm], vm);
+    let value = vm.read(hl![vm]);
     let result = i_rlc_imp(value, vm);
     // Write value
-    mmu::wb(hl![vm], result, vm);
+    vm.write(hl![vm], result);
 
     Clock { m:2, t:16 }
 }
 
 /// Implementation of RRC
-pub fn i_rrc_imp(value : u8, vm : &mut Vm) -> u8 {
+pub fn i_rrc_imp<B : CpuBus>(value : u8, vm : &mut B) -> u8 {
     let result = (value >> 1) | (value << 7);
 
     reset_flags(vm);
@@ -2175,7 +2780,7 @@ pub fn i_rrc_imp(value : u8, vm : &mut Vm) -> u8 {
 /// Bit 0 goes in carry.
 ///
 /// Syntax : `RRC reg:Register`
-pub fn i_rrc(vm : &mut Vm, reg : Register) -> Clock {
+pub fn i_rrc<B : CpuBus>(vm : &mut B, reg : Register) -> Clock {
     reg![vm ; reg] = i_rrc_imp(reg![vm ; reg], vm);
     Clock { m:2, t:8 }
 }
@@ -2188,7 +2793,7 @@ pub fn i_rrc(vm : &mut Vm, reg : Register) -> Clock {
 /// Reset Z flag.
 ///
 /// Syntax : `RRCA`
-pub fn i_rrca(vm : &mut Vm) -> Clock {
+pub fn i_rrca<B : CpuBus>(vm : &mut B) -> Clock {
     i_rrc(vm, Register::A);
     set_flag(vm, Flag::Z, false);
     Clock { m:1, t:4 }
@@ -2200,12 +2805,12 @@ pub fn i_rrca(vm : &mut Vm) -> Clock {
 /// Bit 0 goes in carry.
 ///
 /// Syntax : `RRHLm`
-pub fn i_rrchlm(vm : &mut Vm) -> Clock {
+pub fn i_rrchlm<B : CpuBus>(vm : &mut B) -> Clock {
     // Read value
-    let value = mmu::rb(hl![vm], vm);
+    let value = vm.read(hl![vm]);
     let result = i_rrc_imp(value, vm);
     // Write value
-    mmu::wb(hl![vm], result, vm);
+    vm.write(hl![vm], result);
 
     Clock { m:2, t:16 }
 }
@@ -2213,16 +2818,68 @@ pub fn i_rrchlm(vm : &mut Vm) -> Clock {
 /// Disable Interruptions
 ///
 /// Syntax : `DI`
-pub fn i_di(vm : &mut Vm) -> Clock {
-    vm.cpu.interrupt = InterruptState::IDisableNextInst;
+pub fn i_di<B : CpuBus>(vm : &mut B) -> Clock {
+    vm[CPU].interrupt = InterruptState::IDisableNextInst;
     Clock { m:1, t:4 }
 }
 
+/// Halt the CPU until an interrupt is requested
+///
+/// The timers and the GPU keep running. When an interrupt wake
+/// up the CPU, it is serviced only if IME is enabled, otherwise
+/// the execution continue after the HALT.
+///
+/// With no interrupt enabled in IE, nothing can wake up the CPU :
+/// it is locked (see cpu_locked).
+pub fn i_halt<B : CpuBus>(vm : &mut B) -> Clock {
+    vm[CPU].halted = true;
+    if vm.peek(0xFFFF) & 0x1F == 0 {
+        vm[CPU].locked = true;
+    }
+    Clock { m:1, t:4 }
+}
+
+/// Stop the CPU until a key is pressed
+///
+/// STOP is followed by a byte (usually 0x00). What happen depends on
+/// the keys and the pending interrupts (see the Pan Docs):
+///
+/// > Key held, interrupt pending    : 1 byte, nothing happen
+/// > Key held, no interrupt         : 2 bytes, enter the HALT mode
+/// > No key held, interrupt pending : 1 byte, enter the STOP mode
+/// > No key held, no interrupt      : 2 bytes, enter the STOP mode
+///
+/// Entering the STOP mode reset the divider, and the phase of TIMA.
+/// The timers don't run until the CPU wake up.
+pub fn i_stop<B : CpuBus>(vm : &mut B) -> Clock {
+    let key_held = vm.joypad_lines() & 0x0F != 0x0F;
+    let pending = vm.peek(0xFFFF) & vm.peek(0xFF0F) & 0x1F != 0;
+
+    // The second byte is consumed only without pending interrupt
+    let length = if pending {
+        1
+    } else {
+        read_program_byte(vm);
+        2
+    };
+
+    if !key_held {
+        let cpu = &mut vm[CPU];
+        cpu.stopped = true;
+        cpu.timers.div = 0;
+        cpu.timers.imp_4c = 0;
+        cpu.timers.imp_nc = 0;
+    } else if !pending {
+        vm[CPU].halted = true;
+    }
+    Clock { m:length, t:4 }
+}
+
 /// Enable Interruptions
 ///
 /// Syntax : `DI`
-pub fn i_ei(vm : &mut Vm) -> Clock {
-    vm.cpu.interrupt = InterruptState::IEnableNextInst;
+pub fn i_ei<B : CpuBus>(vm : &mut B) -> Clock {
+    vm[CPU].interrupt = InterruptState::IEnableNextInst;
     Clock { m:1, t:4 }
 }
 
@@ -2230,7 +2887,7 @@ pub fn i_ei(vm : &mut Vm) -> Clock {
 ///
 /// A <- ~A
 /// Syntax : `CPL`
-pub fn i_cpl(vm : &mut Vm) -> Clock {
+pub fn i_cpl<B : CpuBus>(vm : &mut B) -> Clock {
     reg![vm ; Register::A] = !reg![vm ; Register::A];
 
     set_flag(vm, Flag::N, true);
@@ -2243,7 +2900,7 @@ pub fn i_cpl(vm : &mut Vm) -> Clock {
 ///
 /// Carry <- ~Carry
 /// Syntax : `CCF`
-pub fn i_ccf(vm : &mut Vm) -> Clock {
+pub fn i_ccf<B : CpuBus>(vm : &mut B) -> Clock {
     let carry = flag![vm ; Flag::C];
 
     set_flag(vm, Flag::C, !carry);
@@ -2256,10 +2913,10 @@ pub fn i_ccf(vm : &mut Vm) -> Clock {
 /// RST : Push the stack and jump to a predetermined addr
 ///
 /// Syntax : `RST addr:u16`
-pub fn i_rst(vm : &mut Vm, addr : u16) -> Clock {
+pub fn i_rst<B : CpuBus>(vm : &mut B, addr : u16) -> Clock {
     // Push PC on the stack
     sp![vm] = sp![vm].wrapping_sub(2);
-    mmu::ww(sp![vm], pc![vm], vm);
+    vm.write_word(sp![vm], pc![vm]);
 
     // Update PC
     pc![vm] = addr;
@@ -2269,7 +2926,7 @@ pub fn i_rst(vm : &mut Vm, addr : u16) -> Clock {
 /// SCF : Set Carry Flag
 ///
 /// Syntax : `SCF`
-pub fn i_scf(vm : &mut Vm) -> Clock {
+pub fn i_scf<B : CpuBus>(vm : &mut B) -> Clock {
     set_flag(vm, Flag::N, false);
     set_flag(vm, Flag::H, false);
     set_flag(vm, Flag::C, true);
@@ -2279,7 +2936,7 @@ pub fn i_scf(vm : &mut Vm) -> Clock {
 /// Set the bit `bit` of the register `reg`
 ///
 /// Syntax : `SET bit reg`
-pub fn i_set(vm : &mut Vm, bit : u8, reg : Register) -> Clock {
+pub fn i_set<B : CpuBus>(vm : &mut B, bit : u8, reg : Register) -> Clock {
     reg![vm ; reg] = reg![vm ; reg] | (1 << bit);
     Clock { m:2, t:8 }
 }
@@ -2287,10 +2944,10 @@ pub fn i_set(vm : &mut Vm, bit : u8, reg : Register) -> Clock {
 /// Set the bit `bit` of (HL)
 ///
 /// Syntax : `SET bit`
-pub fn i_sethlm(vm : &mut Vm, bit : u8) -> Clock {
-    let value = mmu::rb(hl![vm], vm);
+pub fn i_sethlm<B : CpuBus>(vm : &mut B, bit : u8) -> Clock {
+    let value = vm.read(hl![vm]);
     let result = value | (1 << bit);
-    mmu::wb(hl![vm], result, vm);
+    vm.write(hl![vm], result);
     Clock { m:2, t:16 }
 }
 
@@ -2298,7 +2955,7 @@ pub fn i_sethlm(vm : &mut Vm, bit : u8) -> Clock {
 /// Reset the bit `bit` of the register `reg`
 ///
 /// Syntax : `RES bit reg`
-pub fn i_res(vm : &mut Vm, bit : u8, reg : Register) -> Clock {
+pub fn i_res<B : CpuBus>(vm : &mut B, bit : u8, reg : Register) -> Clock {
     reg![vm ; reg] = reg![vm ; reg] & !(1 << bit);
     Clock { m:2, t:8 }
 }
@@ -2306,10 +2963,10 @@ pub fn i_res(vm : &mut Vm, bit : u8, reg : Register) -> Clock {
 /// Reset the bit `bit` of (HL)
 ///
 /// Syntax : `RES bit`
-pub fn i_reshlm(vm : &mut Vm, bit : u8) -> Clock {
-    let value = mmu::rb(hl![vm], vm);
+pub fn i_reshlm<B : CpuBus>(vm : &mut B, bit : u8) -> Clock {
+    let value = vm.read(hl![vm]);
     let result = value & !(1 << bit);
-    mmu::wb(hl![vm], result, vm);
+    vm.write(hl![vm], result);
     Clock { m:2, t:16 }
 }
 
@@ -2324,16 +2981,16 @@ pub fn i_reshlm(vm : &mut Vm, bit : u8) -> Clock {
 /// and http://forums.nesdev.com/viewtopic.php?t=9088
 ///
 /// Syntax : `DAA`
-pub fn i_daa(vm : &mut Vm) -> Clock {
+pub fn i_daa<B : CpuBus>(vm : &mut B) -> Clock {
     let c = flag![vm ; Flag::C];
     let h = flag![vm ; Flag::H];
 
     let mut result = reg![vm ; Register::A] as u16;
 
-    // In case of a substraction
+    // In case of a substraction (the carry can't be set)
     if flag![vm ; Flag::N] {
-        if h {result = (result - 0x06) & 0xFF};
-        if c {result -= 0x60};
+        if h {result = result.wrapping_sub(0x06) & 0xFF};
+        if c {result = result.wrapping_sub(0x60) & 0xFF};
     }
     // In case of an addition
     else {
@@ -2341,9 +2998,10 @@ pub fn i_daa(vm : &mut Vm) -> Clock {
         if c || result > 0x9F      {result += 0x60};
     }
 
-    reg![vm; Register::A] = result as u8;
+    let a = result as u8;
+    reg![vm; Register::A] = a;
 
-    set_flag(vm, Flag::Z, result == 0);
+    set_flag(vm, Flag::Z, a == 0);
     set_flag(vm, Flag::H, false);
 
     // Carry is unchanged unless there is a carry
@@ -2358,8 +3016,374 @@ pub fn i_daa(vm : &mut Vm) -> Clock {
 ///
 /// This opcode shouldn't be called.
 ///
-/// The emulator just ignore it
-pub fn i_invalid(vm : &mut Vm, opcode : u8) -> Clock {
-    println!("Warning: Invalid opcode 0x{:02X}", opcode);
-    Clock { m:1, t:4 }
+/// Depending on the IllegalBehavior of the Vm, the emulator
+/// ignore it, lock the CPU, or report it without executing it.
+pub fn i_invalid<B : CpuBus>(vm : &mut B, opcode : u8) -> Clock {
+    match vm.illegal_opcode_behavior() {
+        IllegalBehavior::Ignore => {
+            println!("Warning: Invalid opcode 0x{:02X}", opcode);
+            Clock { m:1, t:4 }
+        },
+        IllegalBehavior::Hang => {
+            vm[CPU].locked = true;
+            Clock { m:1, t:4 }
+        },
+        IllegalBehavior::Error => {
+            let cpu = &mut vm[CPU];
+            cpu.registers.pc = cpu.instruction_pc;
+            cpu.illegal_opcode = Some(IllegalOpcode {
+                pc : cpu.instruction_pc,
+                opcode : opcode,
+            });
+            Clock { m:0, t:0 }
+        },
+    }
+}
+
+/////////////////////////////////////////
+//
+// Structured decoding of the instructions
+//
+/////////////////////////////////////////
+
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+/// Name of the 16 bits registers
+pub enum Register16 {
+    AF,
+    BC,
+    DE,
+    HL,
+    SP,
+}
+
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+/// 8 bits operand of ALU and CB instructions : a register or (HL)
+pub enum Operand8 {
+    Reg(Register),
+    HLm,
+}
+
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+/// Memory location used by the loads from / to A
+pub enum Address {
+    /// (BC)
+    BC,
+    /// (DE)
+    DE,
+    /// (HL)
+    HL,
+    /// (HL+)
+    HLInc,
+    /// (HL-)
+    HLDec,
+    /// (a16)
+    Imm(u16),
+    /// (0xFF00 + a8)
+    HighImm(u8),
+    /// (0xFF00 + C)
+    HighC,
+}
+
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+/// Condition of the conditional jumps, calls and returns
+pub enum Condition {
+    NZ,
+    Z,
+    NC,
+    C,
+}
+
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+/// Operation of the 8 bits arithmetic / logic instructions
+pub enum AluOp {
+    Add,
+    Adc,
+    Sub,
+    Sbc,
+    And,
+    Xor,
+    Or,
+    Cp,
+}
+
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+/// Rotations and shifts of the CB table
+pub enum ShiftOp {
+    Rlc,
+    Rrc,
+    Rl,
+    Rr,
+    Sla,
+    Sra,
+    Swap,
+    Srl,
+}
+
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+/// Machine readable representation of an instruction,
+/// with its operands.
+pub enum DecodedInstr {
+    Nop,
+    Stop,
+    Halt,
+    Di,
+    Ei,
+    Daa,
+    Cpl,
+    Scf,
+    Ccf,
+    Rlca,
+    Rrca,
+    Rla,
+    Rra,
+
+    /// LD dst, src
+    LdRR { dst : Register, src : Register },
+    /// LD dst, d8
+    LdRD8 { dst : Register, value : u8 },
+    /// LD dst, (HL)
+    LdRHLm { dst : Register },
+    /// LD (HL), src
+    LdHLmR { src : Register },
+    /// LD (HL), d8
+    LdHLmD8 { value : u8 },
+    /// LD A, (addr)
+    LdAMem { src : Address },
+    /// LD (addr), A
+    LdMemA { dst : Address },
+    /// LD dst, d16
+    LdR16D16 { dst : Register16, value : u16 },
+    /// LD (a16), SP
+    LdA16Sp { addr : u16 },
+    /// LD SP, HL
+    LdSpHl,
+    /// LD HL, SP + r8
+    LdHlSpR8 { offset : i8 },
+    Push(Register16),
+    Pop(Register16),
+
+    /// op A, src
+    Alu { op : AluOp, src : Operand8 },
+    /// op A, d8
+    AluImm { op : AluOp, value : u8 },
+    Inc(Operand8),
+    Dec(Operand8),
+    Inc16(Register16),
+    Dec16(Register16),
+    /// ADD HL, src
+    AddHlR16(Register16),
+    /// ADD SP, r8
+    AddSpR8 { offset : i8 },
+
+    Jr { offset : i8 },
+    JrCc { cc : Condition, offset : i8 },
+    Jp { addr : u16 },
+    JpCc { cc : Condition, addr : u16 },
+    JpHl,
+    Call { addr : u16 },
+    CallCc { cc : Condition, addr : u16 },
+    Ret,
+    RetCc { cc : Condition },
+    Reti,
+    Rst { addr : u16 },
+
+    /// Rotations and shifts (CB table)
+    Shift { op : ShiftOp, target : Operand8 },
+    Bit { bit : u8, target : Operand8 },
+    Res { bit : u8, target : Operand8 },
+    Set { bit : u8, target : Operand8 },
+
+    /// Opcode that doesn't exist
+    Invalid(u8),
+}
+
+/// Decode the instruction at address `addr` of a memory bus
+///
+/// Always peek 3 bytes, the longest instruction length.
+pub fn decode_at<B : mmu::MemoryBus>(bus : &B, addr : u16) -> (DecodedInstr, usize) {
+    let bytes = [bus.peek(addr),
+                 bus.peek(addr.wrapping_add(1)),
+                 bus.peek(addr.wrapping_add(2))];
+    decode(&bytes)
+}
+
+/// Decode the instruction at the beginning of `bytes`
+///
+/// Return the decoded instruction and its length in bytes.
+/// Missing operand bytes (if `bytes` is too short) are read as 0.
+pub fn decode(bytes : &[u8]) -> (DecodedInstr, usize) {
+    let byte = |i : usize| -> u8 { bytes.get(i).cloned().unwrap_or(0) };
+    let word = |i : usize| -> u16 { w_combine(byte(i + 1), byte(i)) };
+
+    // Registers by their index in opcodes (6 is (HL))
+    let r = |i : u8| -> Operand8 {
+        match i & 0x07 {
+            0 => Operand8::Reg(Register::B),
+            1 => Operand8::Reg(Register::C),
+            2 => Operand8::Reg(Register::D),
+            3 => Operand8::Reg(Register::E),
+            4 => Operand8::Reg(Register::H),
+            5 => Operand8::Reg(Register::L),
+            6 => Operand8::HLm,
+            _ => Operand8::Reg(Register::A),
+        }
+    };
+    let rp = |i : u8| -> Register16 {
+        match i & 0x03 {
+            0 => Register16::BC,
+            1 => Register16::DE,
+            2 => Register16::HL,
+            _ => Register16::SP,
+        }
+    };
+    let rp2 = |i : u8| -> Register16 {
+        match i & 0x03 {
+            0 => Register16::BC,
+            1 => Register16::DE,
+            2 => Register16::HL,
+            _ => Register16::AF,
+        }
+    };
+    let cc = |i : u8| -> Condition {
+        match i & 0x03 {
+            0 => Condition::NZ,
+            1 => Condition::Z,
+            2 => Condition::NC,
+            _ => Condition::C,
+        }
+    };
+    let alu = |i : u8| -> AluOp {
+        match i & 0x07 {
+            0 => AluOp::Add,
+            1 => AluOp::Adc,
+            2 => AluOp::Sub,
+            3 => AluOp::Sbc,
+            4 => AluOp::And,
+            5 => AluOp::Xor,
+            6 => AluOp::Or,
+            _ => AluOp::Cp,
+        }
+    };
+
+    let opcode = byte(0);
+    // Decompose the opcode as xxyyyzzz
+    let x = opcode >> 6;
+    let y = (opcode >> 3) & 0x07;
+    let z = opcode & 0x07;
+
+    match opcode {
+        0x00 => (DecodedInstr::Nop, 1),
+        0x10 => (DecodedInstr::Stop, 2),
+        0x76 => (DecodedInstr::Halt, 1),
+        0xF3 => (DecodedInstr::Di, 1),
+        0xFB => (DecodedInstr::Ei, 1),
+        0x27 => (DecodedInstr::Daa, 1),
+        0x2F => (DecodedInstr::Cpl, 1),
+        0x37 => (DecodedInstr::Scf, 1),
+        0x3F => (DecodedInstr::Ccf, 1),
+        0x07 => (DecodedInstr::Rlca, 1),
+        0x0F => (DecodedInstr::Rrca, 1),
+        0x17 => (DecodedInstr::Rla, 1),
+        0x1F => (DecodedInstr::Rra, 1),
+
+        0x08 => (DecodedInstr::LdA16Sp { addr : word(1) }, 3),
+        0x18 => (DecodedInstr::Jr { offset : byte(1) as i8 }, 2),
+        0x20 | 0x28 | 0x30 | 0x38 =>
+            (DecodedInstr::JrCc { cc : cc(y - 4), offset : byte(1) as i8 }, 2),
+        0x02 => (DecodedInstr::LdMemA { dst : Address::BC }, 1),
+        0x12 => (DecodedInstr::LdMemA { dst : Address::DE }, 1),
+        0x22 => (DecodedInstr::LdMemA { dst : Address::HLInc }, 1),
+        0x32 => (DecodedInstr::LdMemA { dst : Address::HLDec }, 1),
+        0x0A => (DecodedInstr::LdAMem { src : Address::BC }, 1),
+        0x1A => (DecodedInstr::LdAMem { src : Address::DE }, 1),
+        0x2A => (DecodedInstr::LdAMem { src : Address::HLInc }, 1),
+        0x3A => (DecodedInstr::LdAMem { src : Address::HLDec }, 1),
+        0x36 => (DecodedInstr::LdHLmD8 { value : byte(1) }, 2),
+
+        0xC3 => (DecodedInstr::Jp { addr : word(1) }, 3),
+        0xC2 | 0xCA | 0xD2 | 0xDA =>
+            (DecodedInstr::JpCc { cc : cc(y), addr : word(1) }, 3),
+        0xE9 => (DecodedInstr::JpHl, 1),
+        0xCD => (DecodedInstr::Call { addr : word(1) }, 3),
+        0xC4 | 0xCC | 0xD4 | 0xDC =>
+            (DecodedInstr::CallCc { cc : cc(y), addr : word(1) }, 3),
+        0xC9 => (DecodedInstr::Ret, 1),
+        0xD9 => (DecodedInstr::Reti, 1),
+        0xC0 | 0xC8 | 0xD0 | 0xD8 => (DecodedInstr::RetCc { cc : cc(y) }, 1),
+
+        0xE0 => (DecodedInstr::LdMemA { dst : Address::HighImm(byte(1)) }, 2),
+        0xF0 => (DecodedInstr::LdAMem { src : Address::HighImm(byte(1)) }, 2),
+        0xE2 => (DecodedInstr::LdMemA { dst : Address::HighC }, 1),
+        0xF2 => (DecodedInstr::LdAMem { src : Address::HighC }, 1),
+        0xEA => (DecodedInstr::LdMemA { dst : Address::Imm(word(1)) }, 3),
+        0xFA => (DecodedInstr::LdAMem { src : Address::Imm(word(1)) }, 3),
+        0xE8 => (DecodedInstr::AddSpR8 { offset : byte(1) as i8 }, 2),
+        0xF8 => (DecodedInstr::LdHlSpR8 { offset : byte(1) as i8 }, 2),
+        0xF9 => (DecodedInstr::LdSpHl, 1),
+
+        0xD3 | 0xDB | 0xDD | 0xE3 | 0xE4 | 0xEB | 0xEC | 0xED | 0xF4 | 0xFC | 0xFD =>
+            (DecodedInstr::Invalid(opcode), 1),
+
+        0xCB => (decode_cb(byte(1)), 2),
+
+        // Regular blocks of the table
+        _ => match (x, z) {
+            (0, 1) if y & 1 == 0 => (DecodedInstr::LdR16D16 { dst : rp(y >> 1), value : word(1) }, 3),
+            (0, 1) => (DecodedInstr::AddHlR16(rp(y >> 1)), 1),
+            (0, 3) if y & 1 == 0 => (DecodedInstr::Inc16(rp(y >> 1)), 1),
+            (0, 3) => (DecodedInstr::Dec16(rp(y >> 1)), 1),
+            (0, 4) => (DecodedInstr::Inc(r(y)), 1),
+            (0, 5) => (DecodedInstr::Dec(r(y)), 1),
+            (0, 6) => match r(y) {
+                Operand8::Reg(dst) => (DecodedInstr::LdRD8 { dst : dst, value : byte(1) }, 2),
+                Operand8::HLm => (DecodedInstr::LdHLmD8 { value : byte(1) }, 2),
+            },
+            (1, _) => match (r(y), r(z)) {
+                (Operand8::Reg(dst), Operand8::Reg(src)) => (DecodedInstr::LdRR { dst : dst, src : src }, 1),
+                (Operand8::Reg(dst), Operand8::HLm) => (DecodedInstr::LdRHLm { dst : dst }, 1),
+                (Operand8::HLm, Operand8::Reg(src)) => (DecodedInstr::LdHLmR { src : src }, 1),
+                (Operand8::HLm, Operand8::HLm) => (DecodedInstr::Halt, 1),
+            },
+            (2, _) => (DecodedInstr::Alu { op : alu(y), src : r(z) }, 1),
+            (3, 1) => (DecodedInstr::Pop(rp2(y >> 1)), 1),
+            (3, 5) => (DecodedInstr::Push(rp2(y >> 1)), 1),
+            (3, 6) => (DecodedInstr::AluImm { op : alu(y), value : byte(1) }, 2),
+            (3, 7) => (DecodedInstr::Rst { addr : (y as u16) * 8 }, 1),
+            _ => (DecodedInstr::Invalid(opcode), 1),
+        },
+    }
+}
+
+/// Decode an instruction of the CB table (the byte following 0xCB)
+pub fn decode_cb(opcode : u8) -> DecodedInstr {
+    let target = match opcode & 0x07 {
+        0 => Operand8::Reg(Register::B),
+        1 => Operand8::Reg(Register::C),
+        2 => Operand8::Reg(Register::D),
+        3 => Operand8::Reg(Register::E),
+        4 => Operand8::Reg(Register::H),
+        5 => Operand8::Reg(Register::L),
+        6 => Operand8::HLm,
+        _ => Operand8::Reg(Register::A),
+    };
+    let y = (opcode >> 3) & 0x07;
+
+    match opcode >> 6 {
+        0 => {
+            let op = match y {
+                0 => ShiftOp::Rlc,
+                1 => ShiftOp::Rrc,
+                2 => ShiftOp::Rl,
+                3 => ShiftOp::Rr,
+                4 => ShiftOp::Sla,
+                5 => ShiftOp::Sra,
+                6 => ShiftOp::Swap,
+                _ => ShiftOp::Srl,
+            };
+            DecodedInstr::Shift { op : op, target : target }
+        },
+        1 => DecodedInstr::Bit { bit : y, target : target },
+        2 => DecodedInstr::Res { bit : y, target : target },
+        _ => DecodedInstr::Set { bit : y, target : target },
+    }
 }
@@ -1,22 +1,40 @@
 use tools::*;
 use vm::*;
+use mmu::*;
+use io;
+use std::cmp;
 
 const SCREEN_WIDTH  : usize = 160;
 const SCREEN_HEIGHT : usize = 144;
 
-#[derive(PartialEq, Eq, Debug)]
+#[derive(PartialEq, Eq, Clone, Debug)]
 /// Represent the memory, registers and flags of the GPU
 pub struct Gpu {
     /// Clock (in cycles) used to switch mode
     pub clock           : u64,
+    /// Duration of the mode 3 (ScanlineVRAM) of the current line
+    pub mode3_cycles    : u64,
     /// Current mode of the GPU
     pub mode            : GpuMode,
     /// Number of the current line
     pub line            : u8,
+    /// LY Compare register
+    pub lyc             : u8,
+    /// Interrupt selection bits (3-6) of the STAT register
+    pub stat            : u8,
+    /// Coincidence flag (bit 2 of STAT), set when LY == LYC
+    pub coincidence     : bool,
     /// Scroll X register
     pub scx             : u8,
     /// Scroll Y register
     pub scy             : u8,
+    /// Window Y position
+    pub wy              : u8,
+    /// Window X position (minus 7)
+    pub wx              : u8,
+    /// Line of the window to draw on the next scanline.
+    /// Only incremented on the lines where the window is displayed.
+    pub window_line     : u8,
     /// Background Palette
     pub bg_palette      : u8,
     /// Object Palette 0
@@ -27,6 +45,11 @@ pub struct Gpu {
     pub lcdc            : LCDC,
     /// Memory used for rendering the current screen
     pub rendering_memory        : Vec<u8>,
+    /// Pixels changed since the beginning of the current frame
+    pub frame_dirty     : Option<Rect>,
+    /// Pixels changed during the last complete frame, None if
+    /// everything changed (see dirty_rect)
+    pub dirty_rect      : Option<Rect>,
     /// Sprite stored in OAM
     /// (duplicate the values in OAM
     /// with easy access for rendering)
@@ -38,21 +61,30 @@ impl Default for Gpu {
     fn default() -> Gpu {
         Gpu {
             clock       : Default::default(),
+            mode3_cycles : 172,
             mode        : GpuMode::ScanlineOAM,
             line        : 0,
+            lyc         : 0,
+            stat        : 0,
+            coincidence : true,
             scx         : 0,
             scy         : 0,
+            wy          : 0,
+            wx          : 0,
+            window_line : 0,
             bg_palette  : 0xFC, // TODO : Check initial values when booting without rom
             obj_palette_0 : 0xFF,
             obj_palette_1 : 0xFF,
             lcdc        : u8_to_lcdc(0x91),
             rendering_memory    : white_memory(0..144*160*3),
+            frame_dirty : None,
+            dirty_rect  : None,
             sprites     : Box::new([Default::default(); 40]),
         }
     }
 }
 
-#[derive(Eq, PartialEq, Debug)]
+#[derive(Eq, PartialEq, Clone, Debug)]
 pub enum GpuMode {
     /// Horizontable blank mode.
     /// Both OAM and VRAM are accessible.
@@ -184,27 +216,37 @@ impl Default for Sprite {
 pub fn update_gpu_mode(vm : &mut Vm, cycles : u64) {
     // Update the clock
     vm.gpu.clock = vm.gpu.clock.wrapping_add(cycles);
+    let line = vm.gpu.line;
+    let mode = vm.gpu.mode.clone();
 
     match vm.gpu.mode {
-        GpuMode::HorizontalBlank if vm.gpu.clock >= 204 => {
-            vm.gpu.clock -= 204;
+        // The HBlank last until the end of the 456 cycles of the line
+        GpuMode::HorizontalBlank if vm.gpu.clock >= 376 - vm.gpu.mode3_cycles => {
+            vm.gpu.clock -= 376 - vm.gpu.mode3_cycles;
             // If it's the last line of the screen
             if vm.gpu.line == 143 {
                 vm.gpu.mode = GpuMode::VerticalBlank;
                 // Activate vertical blank flag in ifr register
                 vm.mmu.ifr.vblank = true;
+                // The next frame start with the first line of the window
+                vm.gpu.window_line = 0;
+                vm.gpu.dirty_rect = Some(vm.gpu.frame_dirty.take().unwrap_or(EMPTY_RECT));
             }
             else {
                 vm.gpu.mode = GpuMode::ScanlineOAM;
             }
             vm.gpu.line += 1;
+            if vm.gpu.mode == GpuMode::ScanlineOAM {
+                fire_scanline_callback(vm);
+            }
         },
         GpuMode::ScanlineOAM if vm.gpu.clock >= 80 => {
             vm.gpu.clock -= 80;
             vm.gpu.mode = GpuMode::ScanlineVRAM;
+            vm.gpu.mode3_cycles = mode3_length(vm);
         },
-        GpuMode::ScanlineVRAM if vm.gpu.clock >= 172 => {
-            vm.gpu.clock -= 172;
+        GpuMode::ScanlineVRAM if vm.gpu.clock >= vm.gpu.mode3_cycles => {
+            vm.gpu.clock -= vm.gpu.mode3_cycles;
             vm.gpu.mode = GpuMode::HorizontalBlank;
             render_scanline(vm);
         },
@@ -215,10 +257,128 @@ pub fn update_gpu_mode(vm : &mut Vm, cycles : u64) {
             if vm.gpu.line == 153 {
                 vm.gpu.line = 0;
                 vm.gpu.mode = GpuMode::ScanlineOAM;
+                fire_scanline_callback(vm);
             }
         },
         _ => return,
     }
+
+    if vm.gpu.mode != mode {
+        update_mode_interrupt(vm);
+    }
+    if vm.gpu.line != line {
+        update_coincidence(vm);
+    }
+}
+
+/// Request a STAT interrupt when entering a mode enabled by STAT
+/// (bit 3 for HBlank, bit 4 for VBlank and bit 5 for OAM).
+fn update_mode_interrupt(vm : &mut Vm) {
+    let enable = match vm.gpu.mode {
+        GpuMode::HorizontalBlank => 0x08,
+        GpuMode::VerticalBlank => 0x10,
+        GpuMode::ScanlineOAM => 0x20,
+        GpuMode::ScanlineVRAM => 0x00,
+    };
+    if vm.gpu.stat & enable != 0 {
+        raise_interrupt(&mut vm.mmu.ifr, Interrupt::LcdStat);
+    }
+}
+
+/// Duration of the mode 3 (pixel transfer) of the current line.
+///
+/// It last at least 172 cycles, plus the pixels discarded because
+/// of the fine scroll (SCX % 8), plus 6 cycles to fetch the window,
+/// plus the penalty of each sprite of the line (see sprite_penalty).
+pub fn mode3_length(vm : &Vm) -> u64 {
+    let lcdc = vm.gpu.lcdc;
+    let mut cycles = 172 + (vm.gpu.scx % 8) as u64;
+
+    if lcdc.background_display && lcdc.window
+        && vm.gpu.line >= vm.gpu.wy && vm.gpu.wx <= 166 {
+        cycles += 6;
+    }
+    if lcdc.sprite_display {
+        let mut fetched_tiles = Vec::new();
+        for i in sprites_on_line(vm) {
+            cycles += sprite_penalty(vm.gpu.sprites[i].x, vm.gpu.scx, &mut fetched_tiles);
+        }
+    }
+
+    cycles
+}
+
+/// Cycles added to the mode 3 by fetching a sprite at `x`
+/// (screen coordinate, OAM X - 8). The sprites must be given
+/// from left to right.
+///
+/// > From: Pan Docs - Rendering, Mode 3 length
+/// >
+/// > An OBJ with X = 0 incurs an 11-dot penalty. Otherwise, consider
+/// > the background tile the leftmost pixel of the OBJ falls in. If
+/// > it was not considered by a previous OBJ, count its pixels strictly
+/// > to the right of this pixel, minus 2 (or 0 if negative). Then
+/// > add a flat 6-dot penalty.
+///
+/// `fetched_tiles` keep the tiles already considered. The window
+/// is ignored: the tiles are always taken from the background.
+pub fn sprite_penalty(x : isize, scx : u8, fetched_tiles : &mut Vec<isize>) -> u64 {
+    if x == -8 {
+        return 11;
+    }
+
+    let pixel = x + scx as isize;
+    let tile = pixel.div_euclid(8);
+    let mut cycles = 6;
+    if !fetched_tiles.contains(&tile) {
+        fetched_tiles.push(tile);
+        let right = 7 - pixel.rem_euclid(8);
+        cycles += if right > 2 {(right - 2) as u64} else {0};
+    }
+    cycles
+}
+
+/// Compare LY and LYC, and request a STAT interrupt
+/// when they become equal (if enabled by bit 6 of STAT).
+///
+/// Should be called each time LY or LYC change.
+pub fn update_coincidence(vm : &mut Vm) {
+    let coincidence = vm.gpu.line == vm.gpu.lyc;
+    if coincidence && !vm.gpu.coincidence && vm.gpu.stat & 0x40 != 0 {
+        raise_interrupt(&mut vm.mmu.ifr, Interrupt::LcdStat);
+    }
+    vm.gpu.coincidence = coincidence;
+}
+
+/// Restart the frame from the first line (write to LY)
+pub fn reset_ly(vm : &mut Vm) {
+    vm.gpu.line = 0;
+    vm.gpu.clock = 0;
+    vm.gpu.mode = GpuMode::ScanlineOAM;
+    vm.gpu.window_line = 0;
+    update_coincidence(vm);
+}
+
+/// Value of the STAT register
+pub fn stat_to_u8(gpu : &Gpu) -> u8 {
+    let mode = match gpu.mode {
+        GpuMode::HorizontalBlank => 0,
+        GpuMode::VerticalBlank => 1,
+        GpuMode::ScanlineOAM => 2,
+        GpuMode::ScanlineVRAM => 3,
+    };
+    // Bit 7 is unused and always read as 1
+    0x80 | gpu.stat | (gpu.coincidence as u8) << 2 | mode
+}
+
+/// Tell if the CPU can access the OAM.
+///
+/// The GPU read the OAM during the modes ScanlineOAM and ScanlineVRAM,
+/// the CPU then read 0xFF and its writes are ignored.
+pub fn oam_accessible(gpu : &Gpu) -> bool {
+    !gpu.lcdc.display
+        || gpu.mode == GpuMode::HorizontalBlank
+        || gpu.mode == GpuMode::VerticalBlank
 }
 
 /// Return a line of 8 pixels from a tile
@@ -229,6 +389,9 @@ pub fn update_gpu_mode(vm : &mut Vm, cycles : u64) {
 ///
 /// If the first argument is set to true, then the tile
 /// is loaded from 0x8000 tilemap.
+/// Otherwise, LCDC's `tile_set` select between the 0x8000
+/// tilemap (index 0 to 255) and the 0x8800 one (index -128 to 127,
+/// tile 0 being at 0x9000). Tiles 128-255 are shared by both.
 pub fn get_tile_pixels_line(is_sprite : bool, lcdc : LCDC, vram : &Vec<u8>, tile_idx : u8, line_idx : u16) -> Vec<u8> {
     // Each tile contain 8 line. Each line is stored in 2 bytes.
     // Therefor each tile contain 8*2 bytes.
@@ -340,6 +503,77 @@ pub fn render_background(out_addr : isize, vm : &mut Vm) -> Vec<u8> {
     return bg_pixel_list;
 }
 
+/// Render the window above the background
+///
+/// First argument is the address where begin the line of pixel
+/// of the rendering buffer. The pixels of `background_pixels`
+/// covered by the window are replaced (for sprite rendering).
+pub fn render_window(out_addr : isize, background_pixels : &mut Vec<u8>, vm : &mut Vm) {
+    let lcdc = vm.gpu.lcdc;
+    if !lcdc.window || vm.gpu.line < vm.gpu.wy || vm.gpu.wx > 166 {
+        return;
+    }
+
+    // The window begin at WX - 7, and is drawn up to the right
+    // border of the screen.
+    let y = vm.gpu.window_line as u16;
+    let start_x = (vm.gpu.wx as isize) - 7;
+    let map_addr = if lcdc.window_tile_map {0x9C00} else {0x9800} - 0x8000;
+    let map_addr = map_addr + (y as usize / 8) * 32;
+
+    for tile_number in 0..(SCREEN_WIDTH / 8 + 1) {
+        let tile_idx = vm.mmu.vram[map_addr + tile_number];
+        let pixels = get_tile_pixels_line(false, lcdc, &vm.mmu.vram, tile_idx, y % 8);
+
+        for (i, pixel) in pixels.into_iter().enumerate() {
+            let x = start_x + (tile_number * 8 + i) as isize;
+            if x < 0 || x >= (SCREEN_WIDTH as isize) {
+                continue;
+            }
+            let x = x as usize;
+            background_pixels[x] = pixel;
+
+            let colored_pixel = compute_u8_from_palette(vm.gpu.bg_palette, pixel);
+            let (r, g, b) = color_to_rgb(u8_to_color(colored_pixel));
+
+            let addr = out_addr as usize + x * 3;
+            vm.gpu.rendering_memory[addr] = r;
+            vm.gpu.rendering_memory[addr + 1] = g;
+            vm.gpu.rendering_memory[addr + 2] = b;
+        }
+    }
+
+    vm.gpu.window_line += 1;
+}
+
+/// Return the index of the sprites displayed on the current line,
+/// by decreasing priority.
+///
+/// Only the 10 first sprites (in OAM order) that intersect the line
+/// are displayed. On DMG, the sprite with the smallest X coordinate
+/// has the priority, and the one with the lowest OAM index
+/// when they have the same X.
+///
+/// While an OAM DMA is running, the DMA owns the OAM bus and
+/// the GPU reads 0xFF from it: no sprite is displayed.
+pub fn sprites_on_line(vm : &Vm) -> Vec<usize> {
+    if io::oam_dma_active(vm) {
+        return Vec::new();
+    }
+
+    let line = vm.gpu.line as isize;
+    let height = if vm.gpu.lcdc.sprite_size {16} else {8};
+
+    let mut sprites : Vec<usize> = (0..40).filter(|&i| {
+        let sprite = vm.gpu.sprites[i];
+        line >= sprite.y && line < sprite.y + height
+    }).take(10).collect();
+
+    // The sort is stable, so equal X keep the OAM order
+    sprites.sort_by_key(|&i| vm.gpu.sprites[i].x);
+    sprites
+}
+
 /// Render sprites above/bellow the background
 ///
 /// The first argument is the adress of the begining
@@ -348,29 +582,14 @@ pub fn render_background(out_addr : isize, vm : &mut Vm) -> Vec<u8> {
 /// that is displayed. 0 means transparency.
 pub fn render_sprite(out_addr : isize, background_pixels : Vec<u8>, vm : &mut Vm) {
     let lcdc = vm.gpu.lcdc;
-    let vram = &vm.mmu.vram;
+    let line = vm.gpu.line as isize;
 
-    // TODO : Sort sprites by X and low addr !
-    //        Then keep only the first 10. Cf : GB documentation on sprites.
+    // For each pixel of the line, the color and the sprite
+    // with the highest priority that isn't transparent.
+    let mut sprite_pixels : Vec<Option<(u8, Sprite)>> = vec![None ; SCREEN_WIDTH];
 
-    // For each sprite of the table
-    for i in 0..40 {
+    for i in sprites_on_line(vm) {
         let sprite = vm.gpu.sprites[i];
-        let line = vm.gpu.line as isize;
-
-        // If the current line do not intersect the sprite, continue
-        if lcdc.sprite_size {
-            if line < sprite.y || line >= sprite.y + 16 {continue;}
-        } else {
-            if line < sprite.y || line >= sprite.y + 8 {continue;}
-        }
-
-        // Select the sprite palette
-        let palette = if sprite.palette {
-            vm.gpu.obj_palette_1
-        } else {
-            vm.gpu.obj_palette_0
-        };
 
         // Get the line of the sprite we want to display
         let mut y =  if sprite.y_flip {
@@ -391,7 +610,7 @@ pub fn render_sprite(out_addr : isize, background_pixels : Vec<u8>, vm : &mut Vm
             sprite.tile_idx
         };
 
-        let pixels = get_tile_pixels_line(true, lcdc, vram, tile_idx, y);
+        let pixels = get_tile_pixels_line(true, lcdc, &vm.mmu.vram, tile_idx, y);
 
         for i in 0..8 {
             // Horizontal flip
@@ -407,38 +626,208 @@ pub fn render_sprite(out_addr : isize, background_pixels : Vec<u8>, vm : &mut Vm
 
             let x = x as usize;
 
-            // Check if the sprite don't have the priority and background
-            // isn't transparent, continue.
-            if !sprite.priority && background_pixels[x] != 0 {continue};
-            // If the sprite is 8x16 and transparent, also continue.
-            if lcdc.sprite_size && pixels[i] == 0 {continue};
+            // The color 0 of a sprite is transparent.
+            if pixels[i] == 0 {continue};
+            // Keep the pixel of the sprite with the highest priority
+            if sprite_pixels[x].is_none() {
+                sprite_pixels[x] = Some((pixels[i], sprite));
+            }
+        }
+    }
 
-            let colored_pixel = compute_u8_from_palette(palette, pixels[i]);
-            let color = u8_to_color(colored_pixel);
-            let (r, g, b) = color_to_rgb(color);
+    for x in 0..SCREEN_WIDTH {
+        let (pixel, sprite) = match sprite_pixels[x] {
+            Some(p) => p,
+            None => continue,
+        };
 
-            let addr = (out_addr as usize) + x * 3;
-            vm.gpu.rendering_memory[addr] = r;
-            vm.gpu.rendering_memory[addr + 1] = g;
-            vm.gpu.rendering_memory[addr + 2] = b;
+        // Check if the sprite don't have the priority and background
+        // isn't transparent, continue.
+        if !sprite.priority && background_pixels[x] != 0 {continue};
+
+        // Select the sprite palette
+        let palette = if sprite.palette {
+            vm.gpu.obj_palette_1
+        } else {
+            vm.gpu.obj_palette_0
+        };
+
+        let colored_pixel = compute_u8_from_palette(palette, pixel);
+        let color = u8_to_color(colored_pixel);
+        let (r, g, b) = color_to_rgb(color);
+
+        let addr = (out_addr as usize) + x * 3;
+        vm.gpu.rendering_memory[addr] = r;
+        vm.gpu.rendering_memory[addr + 1] = g;
+        vm.gpu.rendering_memory[addr + 2] = b;
+    }
+}
+
+/// Render the whole 256x256 background map
+///
+/// The background is rendered with the current tile data, tile map
+/// and background palette, ignoring SCX and SCY. The result is an
+/// RGB buffer (3 bytes per pixel) like the rendering memory.
+///
+/// If `show_viewport` is true, the border of the 160x144 area
+/// displayed on screen is drawn in red.
+pub fn render_bg_map(vm : &Vm, show_viewport : bool) -> Vec<u8> {
+    let vram = &vm.mmu.vram;
+    let lcdc = vm.gpu.lcdc;
+    let map_addr = if lcdc.bg_tile_map {0x9C00} else {0x9800} - 0x8000;
+
+    let mut out = vec![0 ; 256 * 256 * 3];
+
+    for map_y in 0..32 {
+        for map_x in 0..32 {
+            let tile_idx = vram[map_addr + map_y * 32 + map_x];
+            for line in 0..8 {
+                let pixels = get_tile_pixels_line(false, lcdc, vram, tile_idx, line as u16);
+                for (i, pixel) in pixels.iter().enumerate() {
+                    let colored_pixel = compute_u8_from_palette(vm.gpu.bg_palette, *pixel);
+                    let (r, g, b) = color_to_rgb(u8_to_color(colored_pixel));
+
+                    let addr = ((map_y * 8 + line) * 256 + map_x * 8 + i) * 3;
+                    out[addr] = r;
+                    out[addr + 1] = g;
+                    out[addr + 2] = b;
+                }
+            }
+        }
+    }
+
+    if show_viewport {
+        let scx = vm.gpu.scx as usize;
+        let scy = vm.gpu.scy as usize;
+        let mut mark = |x : usize, y : usize| {
+            // The viewport wrap around the map
+            let addr = (((scy + y) % 256) * 256 + (scx + x) % 256) * 3;
+            out[addr] = 0xFF;
+            out[addr + 1] = 0x00;
+            out[addr + 2] = 0x00;
+        };
+        for x in 0..SCREEN_WIDTH {
+            mark(x, 0);
+            mark(x, SCREEN_HEIGHT - 1);
         }
+        for y in 0..SCREEN_HEIGHT {
+            mark(0, y);
+            mark(SCREEN_WIDTH - 1, y);
+        }
+    }
+
+    out
+}
+
+/// Fill the line of pixels starting at `out_addr` with white
+pub fn render_blank_line(out_addr : isize, vm : &mut Vm) {
+    let (r, g, b) = color_to_rgb(GreyScale::WHITE);
+    for x in 0..SCREEN_WIDTH {
+        let addr = out_addr as usize + x * 3;
+        vm.gpu.rendering_memory[addr] = r;
+        vm.gpu.rendering_memory[addr + 1] = g;
+        vm.gpu.rendering_memory[addr + 2] = b;
+    }
+}
+
+/// A rectangle of the screen, in pixels
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub struct Rect {
+    pub x : usize,
+    pub y : usize,
+    pub width : usize,
+    pub height : usize,
+}
+
+/// Rectangle of no pixel
+pub const EMPTY_RECT : Rect = Rect { x : 0, y : 0, width : 0, height : 0 };
+
+/// Smallest rectangle containing `a` and `b`
+pub fn rect_union(a : Rect, b : Rect) -> Rect {
+    let x = cmp::min(a.x, b.x);
+    let y = cmp::min(a.y, b.y);
+    Rect {
+        x : x,
+        y : y,
+        width : cmp::max(a.x + a.width, b.x + b.width) - x,
+        height : cmp::max(a.y + a.height, b.y + b.height) - y,
     }
 }
 
+/// Bounding box of the pixels that changed during the last
+/// complete frame, compared to the frame before.
+///
+/// None if everything changed (before the first frame, or after
+/// debug_set_framebuffer), and EMPTY_RECT if the frame is identical
+/// to the previous one.
+pub fn dirty_rect(vm : &Vm) -> Option<Rect> {
+    vm.gpu.dirty_rect
+}
+
+/// The last frame rendered, as 160x144 RGB pixels
+/// (3 bytes per pixel, line by line).
+pub fn framebuffer(vm : &Vm) -> &[u8] {
+    &vm.gpu.rendering_memory
+}
+
+/// Replace the rendering memory by `pixels` (160x144 RGB pixels),
+/// to test the presentation of a frontend without running a ROM.
+///
+/// The whole screen is reported as dirty.
+/// Panic if `pixels` doesn't have the size of a frame.
+#[cfg(feature = "debug")]
+pub fn debug_set_framebuffer(vm : &mut Vm, pixels : &[u8]) {
+    assert!(pixels.len() == SCREEN_WIDTH * SCREEN_HEIGHT * 3);
+    vm.gpu.rendering_memory.copy_from_slice(pixels);
+    vm.gpu.dirty_rect = None;
+}
+
 /// Render the current line of pixel on the rendering_memory
 pub fn render_scanline(vm : &mut Vm) {
     // Compute the adresse of the current line of pixels to render
     let out_addr = (vm.gpu.line as isize) * (SCREEN_WIDTH as isize) * 3;
+    let start = out_addr as usize;
+    let mut previous = [0u8; SCREEN_WIDTH * 3];
+    previous.copy_from_slice(&vm.gpu.rendering_memory[start..start + SCREEN_WIDTH * 3]);
+
+    draw_scanline(out_addr, vm);
+
+    // Extend the dirty rectangle with the pixels that changed
+    let line = &vm.gpu.rendering_memory[start..start + SCREEN_WIDTH * 3];
+    let changed = |x : &usize| line[x * 3..x * 3 + 3] != previous[x * 3..x * 3 + 3];
+    if let Some(left) = (0..SCREEN_WIDTH).find(&changed) {
+        let right = (0..SCREEN_WIDTH).rev().find(&changed).unwrap();
+        let line_rect = Rect { x : left, y : vm.gpu.line as usize, width : right - left + 1, height : 1 };
+        vm.gpu.frame_dirty = Some(match vm.gpu.frame_dirty {
+            Some(rect) => rect_union(rect, line_rect),
+            None => line_rect,
+        });
+    }
+}
+
+/// Draw the current line of pixel on the rendering_memory,
+/// at the address `out_addr`.
+fn draw_scanline(out_addr : isize, vm : &mut Vm) {
     let lcdc = vm.gpu.lcdc;
 
+    // When the LCD is off, nothing is displayed
+    if !lcdc.display {
+        render_blank_line(out_addr, vm);
+        return;
+    }
+
     //
     // BACKGROUND RENDERING
     //
 
     // Return a list of pixels in the current background line
     let background_pixels = if lcdc.background_display {
-        render_background(out_addr, vm)
+        let mut pixels = render_background(out_addr, vm);
+        render_window(out_addr, &mut pixels, vm);
+        pixels
     } else {
+        // The background is white (color 0) on DMG
+        render_blank_line(out_addr, vm);
         vec![0 ; SCREEN_WIDTH] // Return trensparency if nothing was draw
     };
 
@@ -452,6 +841,61 @@ pub fn render_scanline(vm : &mut Vm) {
     }
 }
 
+/// Render the 384 tiles of the VRAM as a sheet
+///
+/// The tiles are arranged in a grid of 16 tiles by 24, in the order
+/// of the tile data (0x8000-0x97FF). The palettes are ignored: the
+/// colors 0 to 3 are displayed from white to black. The result is an
+/// RGB buffer (3 bytes per pixel) of 128x192 pixels.
+pub fn render_tileset(vm : &Vm) -> Vec<u8> {
+    let vram = &vm.mmu.vram;
+    // Address the tiles 256-383 from 0x9000
+    let lcdc = LCDC { tile_set : false, .. vm.gpu.lcdc };
+
+    let mut out = vec![0 ; 128 * 192 * 3];
+
+    for tile in 0..384 {
+        let (tile_x, tile_y) = (tile % 16, tile / 16);
+        for line in 0..8 {
+            let pixels = if tile < 256 {
+                get_tile_pixels_line(true, lcdc, vram, tile as u8, line as u16)
+            } else {
+                get_tile_pixels_line(false, lcdc, vram, (tile - 256) as u8, line as u16)
+            };
+            for (i, pixel) in pixels.iter().enumerate() {
+                let (r, g, b) = color_to_rgb(u8_to_color(*pixel));
+
+                let addr = ((tile_y * 8 + line) * 128 + tile_x * 8 + i) * 3;
+                out[addr] = r;
+                out[addr + 1] = g;
+                out[addr + 2] = b;
+            }
+        }
+    }
+
+    out
+}
+
+/// Colors (RGB) of the 4 values of a palette register
+pub fn palette_colors(palette : u8) -> [(u8, u8, u8) ; 4] {
+    let color = |value| color_to_rgb(u8_to_color(compute_u8_from_palette(palette, value)));
+    [color(0), color(1), color(2), color(3)]
+}
+
+/// Colors of the background palettes (BGP, the only one on DMG)
+pub fn bg_palettes(vm : &Vm) -> Vec<[(u8, u8, u8) ; 4]> {
+    vec![palette_colors(vm.gpu.bg_palette)]
+}
+
+/// Colors of the sprite palettes (OBP0 and OBP1)
+///
+/// The color 0 of the sprites is transparent, it is
+/// given for completeness.
+pub fn obj_palettes(vm : &Vm) -> Vec<[(u8, u8, u8) ; 4]> {
+    vec![palette_colors(vm.gpu.obj_palette_0),
+         palette_colors(vm.gpu.obj_palette_1)]
+}
+
 /// Take a tile's pixel `value` (value in [|0, 3|]) and give a color
 /// value (value in [|0, 3|]) using `pallette`.
 pub fn compute_u8_from_palette(palette : u8, value : u8) -> u8 {
@@ -7,6 +7,7 @@ Called by the MMU module.
  */
 
 use vm::*;
+use cpu::*;
 use gpu::*;
 use mmu::*;
 
@@ -17,16 +18,31 @@ pub fn dispatch_io_read(addr : usize, vm : &Vm) -> u8 {
         0xFF04 => vm.cpu.timers.div,
         0xFF05 => vm.cpu.timers.tima,
         0xFF06 => vm.cpu.timers.tma,
+        0xFF07 => tac_to_u8(vm.cpu.timers.tac),
         0xFF40 => lcdc_to_u8(vm.gpu.lcdc),
+        0xFF41 => stat_to_u8(&vm.gpu),
         0xFF42 => vm.gpu.scy,
         0xFF43 => vm.gpu.scx,
         0xFF44 => vm.gpu.line,
+        0xFF45 => vm.gpu.lyc,
+        0xFF4A => vm.gpu.wy,
+        0xFF4B => vm.gpu.wx,
         0xFF47 => vm.gpu.bg_palette,
         0xFF48 => vm.gpu.obj_palette_0,
         0xFF49 => vm.gpu.obj_palette_1,
         0xFF00 => read_joypad(vm),
-        0xFF0F => interrupt_to_u8(vm.mmu.ifr),
+        0xFF01 => vm.mmu.sb,
+        // Only the start and clock bits exist
+        0xFF02 => vm.mmu.sc | 0x7E,
+        0xFF50 => if vm.mmu.bios_enabled {0xFE} else {0xFF},
+        // Bits 5-7 are unused and always read as 1
+        0xFF0F => interrupt_to_u8(vm.mmu.ifr) | 0xE0,
         0xFFFF => interrupt_to_u8(vm.mmu.ier),
+        // Unused area between OAM and IO
+        0xFEA0...0xFEFF => 0x00,
+        // Unmapped registers always read as 0xFF
+        _ if !is_mapped_io(addr) => 0xFF,
+        0xFF10...0xFF3F => read_sound(addr, vm),
         _ => {println!("Unimplemented read at {:04X}", addr); 0}, //TODO
     }
 }
@@ -35,24 +51,225 @@ pub fn dispatch_io_write(addr : usize, value :u8, vm : &mut Vm) {
     // TODO Check if io are allowed
     // depending of the state of gpu.gpu_mode:GpuMode.
     match addr {
-        0xFF04 => vm.cpu.timers.div = 0,
+        0xFF04 => write_div(vm),
         0xFF05 => vm.cpu.timers.tima = value, // TODO: expected behavior = ?
         0xFF06 => vm.cpu.timers.tma = value,
+        0xFF07 => vm.cpu.timers.tac = u8_to_tac(value),
         0xFF40 => vm.gpu.lcdc = u8_to_lcdc(value),
+        // The mode and coincidence bits are read only
+        0xFF41 => vm.gpu.stat = value & 0x78,
         0xFF42 => vm.gpu.scy = value,
         0xFF43 => vm.gpu.scx = value,
-        0xFF44 => vm.gpu.line = 0,
+        0xFF44 => reset_ly(vm),
+        0xFF45 => {
+            vm.gpu.lyc = value;
+            update_coincidence(vm);
+        },
         0xFF46 => dma(vm, value),
         0xFF47 => vm.gpu.bg_palette = value,
+        0xFF4A => vm.gpu.wy = value,
+        0xFF4B => vm.gpu.wx = value,
         0xFF48 => vm.gpu.obj_palette_0 = value,
         0xFF49 => vm.gpu.obj_palette_1 = value,
         0xFF00 => write_joypad(vm, value),
+        0xFF01 => vm.mmu.sb = value,
+        0xFF02 => write_serial_control(vm, value),
+        // Once disabled, the bios can't be mapped again
+        0xFF50 => if value != 0 {vm.mmu.bios_enabled = false},
         0xFF0F => vm.mmu.ifr = u8_to_interrupt(value),
         0xFFFF => vm.mmu.ier = u8_to_interrupt(value),
+        // Writes to the unused area and unmapped registers are ignored
+        0xFEA0...0xFEFF => (),
+        _ if !is_mapped_io(addr) => (),
+        0xFF10...0xFF3F => write_sound(addr, value, vm),
         _ => println!("Unimplemented write at {:04X}", addr), //TODO
     }
 }
 
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+/// Name of the IO registers (sound registers excluded)
+pub enum IoReg {
+    /// FF00 Joypad
+    P1,
+    /// FF01 Serial transfer data
+    SB,
+    /// FF02 Serial transfer control
+    SC,
+    /// FF04 Divider
+    DIV,
+    /// FF05 Timer counter
+    TIMA,
+    /// FF06 Timer modulo
+    TMA,
+    /// FF07 Timer control
+    TAC,
+    /// FF0F Interrupt flag
+    IF,
+    /// FF40 LCD control
+    LCDC,
+    /// FF41 LCD status
+    STAT,
+    /// FF42 Scroll Y
+    SCY,
+    /// FF43 Scroll X
+    SCX,
+    /// FF44 Current line
+    LY,
+    /// FF45 Line compare
+    LYC,
+    /// FF46 OAM DMA
+    DMA,
+    /// FF47 Background palette
+    BGP,
+    /// FF48 Object palette 0
+    OBP0,
+    /// FF49 Object palette 1
+    OBP1,
+    /// FF4A Window Y position
+    WY,
+    /// FF4B Window X position (minus 7)
+    WX,
+    /// FFFF Interrupt enable
+    IE,
+}
+
+/// Address of the register `reg`
+pub fn io_reg_addr(reg : IoReg) -> u16 {
+    match reg {
+        IoReg::P1   => 0xFF00,
+        IoReg::SB   => 0xFF01,
+        IoReg::SC   => 0xFF02,
+        IoReg::DIV  => 0xFF04,
+        IoReg::TIMA => 0xFF05,
+        IoReg::TMA  => 0xFF06,
+        IoReg::TAC  => 0xFF07,
+        IoReg::IF   => 0xFF0F,
+        IoReg::LCDC => 0xFF40,
+        IoReg::STAT => 0xFF41,
+        IoReg::SCY  => 0xFF42,
+        IoReg::SCX  => 0xFF43,
+        IoReg::LY   => 0xFF44,
+        IoReg::LYC  => 0xFF45,
+        IoReg::DMA  => 0xFF46,
+        IoReg::BGP  => 0xFF47,
+        IoReg::OBP0 => 0xFF48,
+        IoReg::OBP1 => 0xFF49,
+        IoReg::WY   => 0xFF4A,
+        IoReg::WX   => 0xFF4B,
+        IoReg::IE   => 0xFFFF,
+    }
+}
+
+/// Read the register `reg` (same as reading its address with rb)
+pub fn read_io(vm : &Vm, reg : IoReg) -> u8 {
+    rb(io_reg_addr(reg), vm)
+}
+
+/// Write the register `reg` (same as writing its address with wb)
+pub fn write_io(vm : &mut Vm, reg : IoReg, value : u8) {
+    wb(io_reg_addr(reg), value, vm)
+}
+
+/// Tell if there is a register at the address `addr` of the IO area
+/// (FF00-FF7F and FFFF) of a DMG.
+///
+/// > FF00        P1 (joypad)
+/// > FF01-FF02   Serial transfer
+/// > FF04-FF07   Timers
+/// > FF0F        IF
+/// > FF10-FF26   Sound (except FF15 and FF1F)
+/// > FF30-FF3F   Wave pattern RAM
+/// > FF40-FF4B   LCD
+/// > FF50        Boot ROM disable
+/// > FFFF        IE
+///
+/// The CGB registers (KEY1, VBK, HDMA, RP, palettes, OPRI, SVBK)
+/// don't exist on a DMG: they read 0xFF and ignore writes.
+pub fn is_mapped_io(addr : usize) -> bool {
+    match addr {
+        0xFF00...0xFF02 => true,
+        0xFF04...0xFF07 => true,
+        0xFF0F => true,
+        0xFF15 | 0xFF1F => false,
+        0xFF10...0xFF26 => true,
+        0xFF30...0xFF3F => true,
+        0xFF40...0xFF4B => true,
+        0xFF50 => true,
+        0xFFFF => true,
+        _ => false,
+    }
+}
+
+/// Bits read as 1 for each sound register (FF10-FF2F)
+///
+/// The write only and unused bits of the sound registers
+/// always read as 1.
+pub const SOUND_READ_MASKS : [u8 ; 0x20] = [
+    0x80, 0x3F, 0x00, 0xFF, 0xBF, // NR10-NR14
+    0xFF, 0x3F, 0x00, 0xFF, 0xBF, // NR20-NR24
+    0x7F, 0xFF, 0x9F, 0xFF, 0xBF, // NR30-NR34
+    0xFF, 0xFF, 0x00, 0x00, 0xBF, // NR40-NR44
+    0x00, 0x00, 0x70,             // NR50-NR52
+    0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+];
+
+/// True if the sound is powered on (NR52 bit 7)
+pub fn sound_enabled(vm : &Vm) -> bool {
+    vm.mmu.sound[0x26 - 0x10] & 0x80 != 0
+}
+
+/// Read a sound register or the wave pattern RAM
+pub fn read_sound(addr : usize, vm : &Vm) -> u8 {
+    let index = addr - 0xFF10;
+    match addr {
+        0xFF30...0xFF3F => vm.mmu.sound[index],
+        _ => vm.mmu.sound[index] | SOUND_READ_MASKS[index],
+    }
+}
+
+/// Turn the sound off, and clear all the sound registers
+/// (same as writing 0 to NR52).
+///
+/// The wave pattern RAM is kept.
+pub fn reset_apu(vm : &mut Vm) {
+    for register in vm.mmu.sound[..0x30 - 0x10].iter_mut() {
+        *register = 0;
+    }
+}
+
+/// Write a sound register or the wave pattern RAM
+///
+/// Turning the sound off (NR52 bit 7) clear all the sound
+/// registers, and they ignore the writes until it is turned
+/// on again. The wave pattern RAM is always writable.
+pub fn write_sound(addr : usize, value : u8, vm : &mut Vm) {
+    let index = addr - 0xFF10;
+    match addr {
+        // The status of the channels is read only
+        0xFF26 if value & 0x80 != 0 => vm.mmu.sound[index] = 0x80,
+        0xFF26 => reset_apu(vm),
+        0xFF30...0xFF3F => vm.mmu.sound[index] = value,
+        _ if sound_enabled(vm) => vm.mmu.sound[index] = value,
+        _ => (),
+    }
+}
+
+/// Write the serial control register (SC)
+///
+/// A transfer started with the internal clock (0x81) completes
+/// immediately: the byte of SB is added to the serial output, and
+/// as no other Game Boy is connected, 0xFF is received.
+pub fn write_serial_control(vm : &mut Vm, value : u8) {
+    vm.mmu.sc = value & 0x81;
+    if value & 0x81 == 0x81 {
+        let byte = vm.mmu.sb;
+        vm.serial_output.push(byte);
+        vm.mmu.sb = 0xFF;
+        vm.mmu.sc &= 0x01;
+        raise_interrupt(&mut vm.mmu.ifr, Interrupt::Serial);
+    }
+}
+
 pub fn read_joypad(vm : &Vm) -> u8 {
     if vm.mmu.joyp & 0x30 == 0x10 {
         return vm.joypad_row_buttons | 0x10;
@@ -69,15 +286,35 @@ pub fn read_joypad(vm : &Vm) -> u8 {
 
 pub fn write_joypad(vm : &mut Vm, value : u8) {
     vm.mmu.joyp = (value & 0x30) | (vm.mmu.joyp & 0x0F);
+    update_joypad_lines(vm);
 }
 
+/// Duration of an OAM DMA (160 bytes, one each 4 cycles)
+pub const DMA_CYCLES : u64 = 160 * 4;
+
 pub fn dma(vm : &mut Vm, value : u8) {
+    vm.mmu.dma_cycles = DMA_CYCLES;
+
     // Compute the address value:00
     let addr = (value as u16) << 8;
 
-    // Copy each sprite
+    // Copy each sprite (the DMA can write the OAM in any GPU mode)
     for i in 0..(40 * 4) {
         let byte = rb(addr + i, vm);
-        wb(0xFE00 + i, byte, vm);
+        vm.mmu.oam[i as usize] = byte;
+        update_sprite(i as usize, byte, vm);
     }
 }
+
+/// Advance the OAM DMA by `cycles` cycles
+///
+/// The copy itself is done at once when the DMA start,
+/// only its duration is emulated.
+pub fn update_dma(vm : &mut Vm, cycles : u64) {
+    vm.mmu.dma_cycles = vm.mmu.dma_cycles.saturating_sub(cycles);
+}
+
+/// True while an OAM DMA is running
+pub fn oam_dma_active(vm : &Vm) -> bool {
+    vm.mmu.dma_cycles > 0
+}
@@ -1,3 +1,9 @@
+#[cfg(feature = "png")]
+extern crate png;
+#[cfg(feature = "serde_json")]
+#[macro_use]
+extern crate serde_json;
+
 pub mod tools;
 pub mod mmu;
 pub mod cpu;
@@ -5,6 +11,10 @@ pub mod gpu;
 pub mod cartridge;
 pub mod vm;
 pub mod io;
+pub mod cfg;
+pub mod timing;
+#[cfg(feature = "png")]
+pub mod test_support;
 
 pub use tools::*;
 pub use mmu::*;
@@ -13,3 +23,5 @@ pub use gpu::*;
 pub use cartridge::*;
 pub use vm::*;
 pub use io::*;
+pub use cfg::*;
+pub use timing::*;
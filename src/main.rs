@@ -28,6 +28,13 @@ pub fn render_screen(vm : &mut Vm, renderer : &mut Renderer, texture : &mut Text
     renderer.present();
 }
 
+// Print the bytes sent through the serial port (debug test roms)
+pub fn print_serial_output(vm : &mut Vm) {
+    for byte in vm.serial_output.drain(..) {
+        print!("{}", byte as char);
+    }
+}
+
 // Main function used for profiling
 pub fn main_perf() {
     // Reag arguments (file and remove boot)
@@ -53,13 +60,13 @@ pub fn main_perf() {
     let mut vm = load_rom(file_name).unwrap();
     // Disable bios if asked
     if no_boot_rom {
-        vm.cpu.registers.pc = 0x100;
-        vm.mmu.bios_enabled = false;
+        skip_bios(&mut vm);
     }
 
     //DEBUG
     while vm.cpu.clock.t <= 4190000 * 30 {
         execute_one_instruction(&mut vm);
+        print_serial_output(&mut vm);
     }
 }
 
@@ -106,8 +113,7 @@ pub fn main_shell() {
     let mut vm = load_rom(file_name).unwrap();
     // Disable bios if asked
     if no_boot_rom {
-        vm.cpu.registers.pc = 0x100;
-        vm.mmu.bios_enabled = false;
+        skip_bios(&mut vm);
     }
 
     // Event Loop
@@ -166,6 +172,7 @@ pub fn main_shell() {
         for _ in 0..200 {
             execute_one_instruction(&mut vm);
         }
+        print_serial_output(&mut vm);
 
         // Render screen if we are during vblank period
         // (we don't want to render too often because it would slow down
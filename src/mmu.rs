@@ -20,6 +20,10 @@
 use tools::*;
 use vm::*;
 use io;
+use gpu::*;
+use std::ops::Range;
+use std::cell::RefCell;
+use std::collections::VecDeque;
 
 /// Describe the divers interupt bits in the
 /// interupt (e/f) Register.
@@ -37,6 +41,27 @@ pub struct InterruptFlags {
     pub joypad   : bool,
 }
 
+/// Name of the interrupts, by priority order.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum Interrupt {
+    VBlank,
+    LcdStat,
+    Timer,
+    Serial,
+    Joypad,
+}
+
+/// Set the bit associated to the interrupt `which` in `ir`
+pub fn raise_interrupt(ir : &mut InterruptFlags, which : Interrupt) {
+    match which {
+        Interrupt::VBlank  => ir.vblank = true,
+        Interrupt::LcdStat => ir.lcd_stat = true,
+        Interrupt::Timer   => ir.timer = true,
+        Interrupt::Serial  => ir.serial = true,
+        Interrupt::Joypad  => ir.joypad = true,
+    }
+}
+
 pub fn interrupt_to_u8(ir : InterruptFlags) -> u8 {
     return (ir.vblank as u8) << 0
         | (ir.lcd_stat as u8) << 1
@@ -74,6 +99,9 @@ pub struct Mmu {
     pub swram : Vec<u8>,
     /// FE00-FE9F    Sprite Attribute Table (OAM)
     pub oam   : Vec<u8>,
+    /// FF10-FF3F    Sound registers and wave pattern RAM.
+    /// No APU is emulated, the values are only stored.
+    pub sound : Vec<u8>,
     /// FF80-FFFE    High RAM (HRAM)
     pub hram  : Vec<u8>,
     /// FFFF         Interrupt Enable Register
@@ -81,12 +109,20 @@ pub struct Mmu {
     /// FF0F         Interrupt Flag Register
     pub ifr   : InterruptFlags,
     /// When true, reading below 0x100 access the bios.
-    /// Once the booting sequence is finished, the value is
-    /// turned to false. Then, rading below 0x100 read bytes from the rom field.
+    /// The booting sequence ends by writing a non zero value
+    /// to FF50, which turn the value to false for good.
+    /// Then, rading below 0x100 read bytes from the rom field.
     pub bios_enabled : bool,
 
     /// JOYPAD register (P1)
     pub joyp  : u8,
+    /// Serial transfer data (SB)
+    pub sb    : u8,
+    /// Serial transfer control (SC)
+    pub sc    : u8,
+
+    /// Cycles left before the end of the current OAM DMA
+    pub dma_cycles : u64,
 }
 
 impl Default for Mmu {
@@ -116,18 +152,31 @@ impl Default for Mmu {
         wram  : empty_memory(0xC000..0xD000),
         swram : empty_memory(0xD000..0xE000),
         oam   : empty_memory(0xFE00..0xFEA0),
+        sound : empty_memory(0xFF10..0xFF40),
         hram  : empty_memory(0xFF80..0xFFFF),
         ier   : Default::default(),
         ifr   : Default::default(),
         bios_enabled : true,
 
         joyp  : 0x3F,
+        sb    : 0x00,
+        sc    : 0x00,
+        dma_cycles : 0,
     }
     }
 }
 
 /// Read a byte from MMU (TODO)
 pub fn rb(addr : u16, vm : &Vm) -> u8 {
+    let value = peek(addr, vm);
+    log_access(addr, value, false, vm);
+    value
+}
+
+/// Read a byte like rb, but without logging the access
+/// (see enable_access_log). For debuggers and other tools
+/// inspecting the memory.
+pub fn peek(addr : u16, vm : &Vm) -> u8 {
     let addr = addr as usize;
     let mmu = &vm.mmu;
     // TODO Check if memory (vram / OAM) is acessible
@@ -145,7 +194,10 @@ pub fn rb(addr : u16, vm : &Vm) -> u8 {
         0xD000...0xDFFF => mmu.swram[addr - 0xD000],
         0xE000...0xEFFF => mmu.wram[addr - 0xE000],
         0xF000...0xFDFF => mmu.swram[addr - 0xF000],
-        0xFE00...0xFE9F => mmu.oam[addr - 0xFE00],
+        0xFE00...0xFE9F => if oam_accessible(&vm.gpu) {mmu.oam[addr - 0xFE00]}
+        else {
+            0xFF
+        },
         0xFF80...0xFFFE => mmu.hram[addr - 0xFF80],
         // Otherwise, it should be an IO
         _ => io::dispatch_io_read(addr, vm),
@@ -154,14 +206,15 @@ pub fn rb(addr : u16, vm : &Vm) -> u8 {
 
 /// Read a word (2 bytes) from MMU at address addr
 pub fn rw(addr : u16, vm : &Vm) -> u16 {
+    audit_wrap(addr, "rw", vm);
     let l = rb(addr, vm);
-    let h = rb(addr + 1, vm);
+    let h = rb(addr.wrapping_add(1), vm);
     w_combine(h, l)
 }
 
-static mut debug :u8 = 0;
 /// Write a byte to the MMU at address addr (TODO)
 pub fn wb(addr : u16, value : u8, vm : &mut Vm) {
+    log_access(addr, value, true, vm);
     let addr = addr as usize;
     // TODO Check if memory (vram / OAM) is acessible
     // depending of the state of gpu.gpu_mode:GpuMode.
@@ -173,6 +226,8 @@ pub fn wb(addr : u16, value : u8, vm : &mut Vm) {
         0xD000...0xDFFF => vm.mmu.swram[addr - 0xD000] = value,
         0xE000...0xEFFF => vm.mmu.wram[addr - 0xE000] = value,
         0xF000...0xFDFF => vm.mmu.swram[addr - 0xF000] = value,
+        // The GPU use the OAM during mode 2 and 3
+        0xFE00...0xFE9F if !oam_accessible(&vm.gpu) => return,
         0xFE00...0xFE9F => {
             let index = addr - 0xFE00;
             vm.mmu.oam[index] = value;
@@ -182,21 +237,200 @@ pub fn wb(addr : u16, value : u8, vm : &mut Vm) {
         // Otherwise, it should be an IO
         _ => io::dispatch_io_write(addr, value, vm),
     }
-    if addr == 0xFF01 {unsafe {
-        debug = value;}
-    }
-    // Debug test roms
-    if addr == 0xFF02 && value == 0x81 {unsafe {
-        print!("{}", debug as char);
-    }}
-
 }
 
 /// Write a word (2 bytes) into the MMU at adress addr
 pub fn ww(addr : u16, value : u16, vm : &mut Vm) {
+    audit_wrap(addr, "ww", vm);
     let (h, l) = w_uncombine(value);
     wb(addr, l, vm);
-    wb(addr + 1, h, vm);
+    wb(addr.wrapping_add(1), h, vm);
+}
+
+/// A memory bus the CPU can read from and write to
+///
+/// The Vm implement it with `rb` and `wb`. Tests can provide
+/// a simpler bus, backed by an array.
+pub trait MemoryBus {
+    /// Read a byte at address addr
+    fn read(&self, addr : u16) -> u8;
+
+    /// Write a byte at address addr
+    fn write(&mut self, addr : u16, value : u8);
+
+    /// Read a byte at address addr, for inspection only
+    /// (the Vm doesn't log it, see peek).
+    fn peek(&self, addr : u16) -> u8 {
+        self.read(addr)
+    }
+
+    /// Read a word (little endian) at address addr
+    fn read_word(&self, addr : u16) -> u16 {
+        let l = self.read(addr);
+        let h = self.read(addr.wrapping_add(1));
+        w_combine(h, l)
+    }
+
+    /// Write a word (little endian) at address addr
+    fn write_word(&mut self, addr : u16, value : u16) {
+        let (h, l) = w_uncombine(value);
+        self.write(addr, l);
+        self.write(addr.wrapping_add(1), h);
+    }
+}
+
+impl MemoryBus for Vm {
+    fn read(&self, addr : u16) -> u8 {
+        rb(addr, self)
+    }
+
+    fn write(&mut self, addr : u16, value : u8) {
+        wb(addr, value, self)
+    }
+
+    fn peek(&self, addr : u16) -> u8 {
+        peek(addr, self)
+    }
+
+    fn read_word(&self, addr : u16) -> u16 {
+        rw(addr, self)
+    }
+
+    fn write_word(&mut self, addr : u16, value : u16) {
+        ww(addr, value, self)
+    }
+}
+
+/// Report a word access at `addr` wrapping around the address
+/// space to the audit callback (only with the `audit` feature).
+#[cfg(feature = "audit")]
+fn audit_wrap(addr : u16, operation : &'static str, vm : &Vm) {
+    if addr == 0xFFFF {
+        if let Some(ref callback) = vm.audit_callback.0 {
+            callback(vm.cpu.registers.pc, operation);
+        }
+    }
+}
+
+#[cfg(not(feature = "audit"))]
+fn audit_wrap(_ : u16, _ : &'static str, _ : &Vm) {}
+
+/// Maximum number of accesses kept in the access log
+pub const ACCESS_LOG_SIZE : usize = 0x10000;
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+/// A read or a write of the memory
+pub struct Access {
+    pub addr : u16,
+    pub value : u8,
+    pub write : bool,
+    /// Address of the instruction doing the access
+    pub pc : u16,
+    /// Value of the CPU clock (in cycles) at the beginning of
+    /// the instruction
+    pub cycle : u64,
+}
+
+/// Record the accesses to a region of the memory
+/// (see enable_access_log).
+#[derive(PartialEq, Eq, Default, Debug)]
+pub struct AccessLog {
+    /// Logged addresses, None when disabled
+    pub region : Option<Range<u16>>,
+    /// Last accesses, from the oldest to the most recent.
+    /// Reads are logged from rb, which doesn't mutate the Vm.
+    pub entries : RefCell<VecDeque<Access>>,
+}
+
+/// Start logging the accesses to `region`.
+///
+/// Only the last ACCESS_LOG_SIZE accesses are kept.
+pub fn enable_access_log(vm : &mut Vm, region : Range<u16>) {
+    vm.access_log.region = Some(region);
+    vm.access_log.entries.borrow_mut().clear();
+}
+
+/// Stop logging the accesses (the log is kept).
+pub fn disable_access_log(vm : &mut Vm) {
+    vm.access_log.region = None;
+}
+
+/// Accesses logged since enable_access_log, from the oldest.
+pub fn access_log(vm : &Vm) -> Vec<Access> {
+    vm.access_log.entries.borrow().iter().cloned().collect()
+}
+
+/// Add an access to the log if it's in the logged region
+fn log_access(addr : u16, value : u8, write : bool, vm : &Vm) {
+    let in_region = match vm.access_log.region {
+        Some(ref region) => region.start <= addr && addr < region.end,
+        None => return,
+    };
+    if !in_region {
+        return;
+    }
+
+    let mut entries = vm.access_log.entries.borrow_mut();
+    if entries.len() == ACCESS_LOG_SIZE {
+        entries.pop_front();
+    }
+    entries.push_back(Access {
+        addr : addr,
+        value : value,
+        write : write,
+        pc : vm.cpu.instruction_pc,
+        cycle : vm.cpu.clock.t,
+    });
+}
+
+#[derive(PartialEq, Eq, Clone, Debug)]
+/// Description of a region of the memory map
+pub struct MemoryRegion {
+    pub name : &'static str,
+    /// First address of the region
+    pub start : u16,
+    /// Last address of the region (included)
+    pub end : u16,
+    /// Bank currently mapped in the region
+    pub bank : usize,
+    /// False if the writes are ignored (or handled by the cartridge)
+    pub writable : bool,
+}
+
+/// Describe each region of the memory map, as seen by rb / wb.
+///
+/// Without MBC support, the ROM banks 0 and 1 are always mapped.
+pub fn memory_regions(vm : &Vm) -> Vec<MemoryRegion> {
+    let region = |name, start, end, bank, writable| MemoryRegion {
+        name : name,
+        start : start,
+        end : end,
+        bank : bank,
+        writable : writable,
+    };
+
+    let mut regions = Vec::new();
+    if vm.mmu.bios_enabled {
+        regions.push(region("BIOS", 0x0000, 0x00FF, 0, false));
+        regions.push(region("ROM Bank 00", 0x0100, 0x3FFF, 0, false));
+    }
+    else {
+        regions.push(region("ROM Bank 00", 0x0000, 0x3FFF, 0, false));
+    }
+    regions.extend(vec![
+        region("ROM Bank NN", 0x4000, 0x7FFF, 1, false),
+        region("Video RAM", 0x8000, 0x9FFF, 0, true),
+        region("External RAM", 0xA000, 0xBFFF, 0, true),
+        region("Work RAM Bank 0", 0xC000, 0xCFFF, 0, true),
+        region("Work RAM Bank 1", 0xD000, 0xDFFF, 1, true),
+        region("Work RAM (Shadow)", 0xE000, 0xFDFF, 0, true),
+        region("Sprite Attribute Table", 0xFE00, 0xFE9F, 0, true),
+        region("Not Usable", 0xFEA0, 0xFEFF, 0, false),
+        region("I/O Ports", 0xFF00, 0xFF7F, 0, true),
+        region("High RAM", 0xFF80, 0xFFFE, 0, true),
+        region("Interrupt Enable Register", 0xFFFF, 0xFFFF, 0, true),
+    ]);
+    regions
 }
 
 /// Update the duplicated representation of a sprite
@@ -0,0 +1,44 @@
+/** Test support Module (Export the rendered frames)
+
+Write the rendering memory into PNG files, so that golden image
+tests can compare a frame with a reference image.
+Only available with the `png` feature.
+ */
+
+use std::fs::File;
+use std::io::{BufWriter, Result, Error, ErrorKind};
+use std::path::Path;
+use png;
+use vm::*;
+
+/// Width and height of the frames
+const FRAME_SIZE : (u32, u32) = (160, 144);
+
+/// Write the current frame (rendering memory) into a RGB PNG file
+pub fn save_frame_png(vm : &Vm, path : &Path) -> Result<()> {
+    let file = try!(File::create(path));
+    let mut encoder = png::Encoder::new(BufWriter::new(file), FRAME_SIZE.0, FRAME_SIZE.1);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    let mut writer = try!(encoder.write_header().map_err(|e| Error::new(ErrorKind::Other, e)));
+    try!(writer.write_image_data(&vm.gpu.rendering_memory)
+         .map_err(|e| Error::new(ErrorKind::Other, e)));
+    Ok(())
+}
+
+/// Read a PNG file written by save_frame_png.
+///
+/// The result has the same layout as the rendering memory.
+pub fn load_frame_png(path : &Path) -> Result<Vec<u8>> {
+    let decoder = png::Decoder::new(try!(File::open(path)));
+    let mut reader = try!(decoder.read_info().map_err(|e| Error::new(ErrorKind::Other, e)));
+
+    let mut pixels = vec![0 ; reader.output_buffer_size()];
+    let info = try!(reader.next_frame(&mut pixels).map_err(|e| Error::new(ErrorKind::Other, e)));
+    if (info.width, info.height) != FRAME_SIZE || info.color_type != png::ColorType::Rgb {
+        return Err(Error::new(ErrorKind::Other, "Not a RGB frame of 160x144 pixels"));
+    }
+    pixels.truncate(info.buffer_size());
+    Ok(pixels)
+}
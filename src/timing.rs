@@ -0,0 +1,76 @@
+/** Timing Module (Pace the emulation at the real speed)
+
+Helpers for the frontends, to run one frame of emulation
+every ~16.74ms (59.7275Hz).
+ */
+
+use std::time::{Duration, Instant};
+use std::thread;
+
+/// Frequency of the CPU clock, in cycles per second
+pub const CPU_FREQUENCY : u64 = 4194304;
+/// Number of cycles to draw a frame (154 lines of 456 cycles)
+pub const CYCLES_PER_FRAME : u64 = 70224;
+/// Duration of a frame, in nanoseconds
+pub const FRAME_DURATION_NS : u64 = CYCLES_PER_FRAME * 1_000_000_000 / CPU_FREQUENCY;
+
+/// Source of time used by the Pacer.
+///
+/// Allow to replace the system clock (for example in tests).
+pub trait TimeSource {
+    /// Time elapsed since an arbitrary origin
+    fn now(&self) -> Duration;
+    /// Block during `duration`
+    fn sleep(&mut self, duration : Duration);
+}
+
+/// The system clock
+pub struct SystemTimeSource {
+    origin : Instant,
+}
+
+impl Default for SystemTimeSource {
+    fn default() -> SystemTimeSource {
+        SystemTimeSource { origin : Instant::now() }
+    }
+}
+
+impl TimeSource for SystemTimeSource {
+    fn now(&self) -> Duration {
+        self.origin.elapsed()
+    }
+
+    fn sleep(&mut self, duration : Duration) {
+        thread::sleep(duration);
+    }
+}
+
+/// Keep track of the beginning of the current frame
+#[derive(Default)]
+pub struct Pacer<T : TimeSource> {
+    pub time : T,
+    /// Time at which the current frame began
+    pub frame_start : Duration,
+}
+
+/// Mark the beginning of a new frame
+pub fn pacer_frame_start<T : TimeSource>(pacer : &mut Pacer<T>) {
+    pacer.frame_start = pacer.time.now();
+}
+
+/// Time left before the end of the current frame.
+///
+/// If the frame took longer than expected, there is nothing to wait.
+pub fn pacer_time_to_wait<T : TimeSource>(pacer : &Pacer<T>) -> Duration {
+    let elapsed = pacer.time.now() - pacer.frame_start;
+    let frame = Duration::from_nanos(FRAME_DURATION_NS);
+    frame.checked_sub(elapsed).unwrap_or(Duration::from_secs(0))
+}
+
+/// Sleep until the end of the current frame
+pub fn pacer_wait<T : TimeSource>(pacer : &mut Pacer<T>) {
+    let duration = pacer_time_to_wait(pacer);
+    if duration > Duration::from_secs(0) {
+        pacer.time.sleep(duration);
+    }
+}
@@ -2,6 +2,9 @@ use cpu::*;
 use mmu::*;
 use gpu::*;
 use cartridge::*;
+use std::fmt;
+use std::cmp;
+use std::collections::VecDeque;
 
 #[derive(PartialEq, Eq, Default, Debug)]
 pub struct Vm {
@@ -14,6 +17,319 @@ pub struct Vm {
     pub joypad_row_cross   : u8,
     /// Keypad column P15 for Start, Select, B, A
     pub joypad_row_buttons : u8,
+    /// Last state of the lines P10-P13, used to detect
+    /// the high to low transitions (see update_joypad_lines).
+    pub joypad_lines : u8,
+
+    /// Bytes sent through the serial port (see write_serial_control).
+    /// Never emptied by the emulator: the host must drain it.
+    pub serial_output : Vec<u8>,
+
+    /// Called at the beginning of each visible scanline
+    /// with the number of the line (see set_scanline_callback).
+    pub scanline_callback : Hook<dyn FnMut(u8, &Vm)>,
+    /// When true, the timers and the GPU are not updated
+    /// (see set_peripherals_frozen).
+    pub peripherals_frozen : bool,
+    /// Called with PC and the name of the operation when a memory
+    /// access wraps around the address space (see set_audit_callback).
+    pub audit_callback : Hook<dyn Fn(u16, &'static str)>,
+    /// Snapshots taken for rewinding (see enable_rewind).
+    pub rewind : Rewind,
+    /// Accesses to the memory (see enable_access_log).
+    pub access_log : AccessLog,
+    /// Called each time an interrupt is serviced
+    /// (see set_interrupt_callback).
+    pub interrupt_callback : Hook<dyn FnMut(Interrupt, u16)>,
+    /// Last executed instructions (see enable_trace_ring).
+    pub trace_ring : TraceRing,
+    /// Bitset of the addresses of the executed instructions,
+    /// empty when disabled (see enable_executed_map).
+    pub executed_map : Vec<u64>,
+    /// Maximum number of instructions executed by a call to
+    /// run_to_vblank (see set_instruction_budget).
+    pub instruction_budget : Option<u64>,
+    /// What to do with the illegal opcodes
+    /// (see set_illegal_opcode_behavior).
+    pub illegal_opcode_behavior : IllegalBehavior,
+    /// Number of frames completed since the power on
+    pub frame_count : u64,
+    /// Called with the rendering memory when a frame is ready
+    /// to be presented (see set_frame_callback).
+    pub frame_callback : Hook<dyn FnMut(&[u8])>,
+    /// Present only one frame out of `present_interval`
+    /// (see set_present_interval).
+    pub present_interval : u32,
+}
+
+/// Copy of the emulated state of a Vm
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct Snapshot {
+    pub cpu : Cpu,
+    pub mmu : Mmu,
+    pub gpu : Gpu,
+    pub joypad_row_cross   : u8,
+    pub joypad_row_buttons : u8,
+    pub joypad_lines       : u8,
+}
+
+/// Ring buffer of the last snapshots, taken every few frames
+#[derive(PartialEq, Eq, Default, Debug)]
+pub struct Rewind {
+    /// Maximum number of snapshots kept (0 disable the rewind)
+    pub capacity : usize,
+    /// Number of frames between two snapshots
+    pub interval_frames : u32,
+    /// Frames completed since the last snapshot
+    pub frames : u32,
+    /// Snapshots, from the oldest to the most recent
+    pub snapshots : VecDeque<Snapshot>,
+}
+
+/// A callback registered by the host (debugger, frontend, ...)
+///
+/// Hooks are not part of the emulated hardware. They always
+/// compare equal, so that comparing two Vm only compare their
+/// emulated state.
+pub struct Hook<F : ?Sized>(pub Option<Box<F>>);
+
+impl<F : ?Sized> Default for Hook<F> {
+    fn default() -> Hook<F> { Hook(None) }
+}
+
+impl<F : ?Sized> PartialEq for Hook<F> {
+    fn eq(&self, _ : &Hook<F>) -> bool { true }
+}
+
+impl<F : ?Sized> Eq for Hook<F> {}
+
+impl<F : ?Sized> fmt::Debug for Hook<F> {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        match self.0 {
+            Some(_) => write!(f, "Hook(Some)"),
+            None    => write!(f, "Hook(None)"),
+        }
+    }
+}
+
+/// Put the Vm in the state left by the bios, without running it.
+///
+/// > From: Pan Docs - Power Up Sequence
+/// >
+/// > AF=01B0 BC=0013 DE=00D8 HL=014D SP=FFFE PC=0100
+/// > TIMA=00 TMA=00 TAC=00 LCDC=91 SCY=00 SCX=00 LYC=00
+/// > BGP=FC WY=00 WX=00 IE=00
+///
+/// The bios also leave the VBlank interrupt requested.
+pub fn skip_bios(vm : &mut Vm) {
+    vm.cpu.registers = Default::default();
+    vm.cpu.registers.pc = 0x100;
+    vm.cpu.interrupt = InterruptState::IDisabled;
+
+    vm.cpu.timers.tima = 0x00;
+    vm.cpu.timers.tma = 0x00;
+    vm.cpu.timers.tac = Default::default();
+
+    vm.gpu.lcdc = u8_to_lcdc(0x91);
+    vm.gpu.scy = 0x00;
+    vm.gpu.scx = 0x00;
+    vm.gpu.lyc = 0x00;
+    vm.gpu.bg_palette = 0xFC;
+    vm.gpu.wy = 0x00;
+    vm.gpu.wx = 0x00;
+
+    // Sound registers initialised by the bios
+    vm.mmu.sound[0x26 - 0x10] = 0x80;
+    vm.mmu.sound[0x11 - 0x10] = 0x80;
+    vm.mmu.sound[0x12 - 0x10] = 0xF3;
+    vm.mmu.sound[0x25 - 0x10] = 0xF3;
+    vm.mmu.sound[0x24 - 0x10] = 0x77;
+    // Frequency of the second note of the logo sound
+    vm.mmu.sound[0x13 - 0x10] = 0xC1;
+
+    vm.mmu.ifr = u8_to_interrupt(0x01);
+    vm.mmu.ier = u8_to_interrupt(0x00);
+    vm.mmu.bios_enabled = false;
+}
+
+/// Register a function called at the beginning of each visible
+/// scanline (when the GPU enter the ScanlineOAM mode).
+///
+/// The callback receive the number of the line (0 to 143) and
+/// the Vm, so that it can log the registers (SCX, SCY, palettes...)
+/// used for each line. Usefull to debug raster effects.
+pub fn set_scanline_callback(vm : &mut Vm, callback : Box<dyn FnMut(u8, &Vm)>) {
+    vm.scanline_callback = Hook(Some(callback));
+}
+
+/// Call the scanline callback, if any, with the current line.
+pub fn fire_scanline_callback(vm : &mut Vm) {
+    if let Some(mut callback) = vm.scanline_callback.0.take() {
+        callback(vm.gpu.line, vm);
+        vm.scanline_callback.0 = Some(callback);
+    }
+}
+
+/// Register a function called each time a memory access wraps
+/// around the address space (reading a word at 0xFFFF, ...).
+///
+/// The callback receive the value of PC when the access happen
+/// (after the opcode was fetched) and the name of the operation.
+/// Such a wrap is usually a bug of the emulation rather than
+/// the expected behavior of a game. The accesses are only
+/// checked when the `audit` feature is enabled.
+pub fn set_audit_callback(vm : &mut Vm, callback : Box<dyn Fn(u16, &'static str)>) {
+    vm.audit_callback = Hook(Some(callback));
+}
+
+/// Register a function called each time an interrupt is serviced
+///
+/// The callback receive the interrupt and the return address
+/// pushed on the stack (the value of PC when it happened).
+pub fn set_interrupt_callback(vm : &mut Vm, callback : Box<dyn FnMut(Interrupt, u16)>) {
+    vm.interrupt_callback = Hook(Some(callback));
+}
+
+/// Call the interrupt callback, if any.
+pub fn fire_interrupt_callback(vm : &mut Vm, which : Interrupt, return_addr : u16) {
+    if let Some(callback) = vm.interrupt_callback.0.as_mut() {
+        callback(which, return_addr);
+    }
+}
+
+/// Register a function called with the rendering memory each
+/// time a frame is ready to be presented (see set_present_interval).
+pub fn set_frame_callback(vm : &mut Vm, callback : Box<dyn FnMut(&[u8])>) {
+    vm.frame_callback = Hook(Some(callback));
+}
+
+/// Present only one frame out of `n` to the frame callback.
+///
+/// The emulation still run every frame: a host can display 30 frames
+/// per second with an interval of 2. An interval of 0 or 1 present
+/// every frame.
+pub fn set_present_interval(vm : &mut Vm, n : u32) {
+    vm.present_interval = n;
+}
+
+/// Called at the end of each frame, count it and call
+/// the frame callback if the frame has to be presented.
+pub fn present_frame_end(vm : &mut Vm) {
+    vm.frame_count += 1;
+    let interval = cmp::max(vm.present_interval, 1) as u64;
+    if vm.frame_count % interval != 0 {
+        return;
+    }
+    if let Some(callback) = vm.frame_callback.0.as_mut() {
+        callback(&vm.gpu.rendering_memory);
+    }
+}
+
+/// Take a snapshot of the Vm every `interval_frames` frames,
+/// keeping the last `capacity` ones (see rewind).
+///
+/// A capacity of 0 disable the rewind.
+pub fn enable_rewind(vm : &mut Vm, capacity : usize, interval_frames : u32) {
+    vm.rewind = Rewind {
+        capacity : capacity,
+        interval_frames : interval_frames,
+        .. Default::default()
+    };
+}
+
+/// Copy the emulated state of the Vm
+pub fn take_snapshot(vm : &Vm) -> Snapshot {
+    Snapshot {
+        cpu : vm.cpu.clone(),
+        mmu : vm.mmu.clone(),
+        gpu : vm.gpu.clone(),
+        joypad_row_cross : vm.joypad_row_cross,
+        joypad_row_buttons : vm.joypad_row_buttons,
+        joypad_lines : vm.joypad_lines,
+    }
+}
+
+/// Replace the emulated state of the Vm by `snapshot`
+pub fn restore_snapshot(vm : &mut Vm, snapshot : Snapshot) {
+    vm.cpu = snapshot.cpu;
+    vm.mmu = snapshot.mmu;
+    vm.gpu = snapshot.gpu;
+    vm.joypad_row_cross = snapshot.joypad_row_cross;
+    vm.joypad_row_buttons = snapshot.joypad_row_buttons;
+    vm.joypad_lines = snapshot.joypad_lines;
+}
+
+/// Called at the end of each frame, take a snapshot if needed.
+pub fn rewind_frame_end(vm : &mut Vm) {
+    if vm.rewind.capacity == 0 {
+        return;
+    }
+
+    vm.rewind.frames += 1;
+    if vm.rewind.frames >= vm.rewind.interval_frames {
+        vm.rewind.frames = 0;
+        if vm.rewind.snapshots.len() == vm.rewind.capacity {
+            vm.rewind.snapshots.pop_front();
+        }
+        let snapshot = take_snapshot(vm);
+        vm.rewind.snapshots.push_back(snapshot);
+    }
+}
+
+/// Go back to the most recent snapshot, and forget it.
+///
+/// Return false if there is no snapshot left.
+pub fn rewind(vm : &mut Vm) -> bool {
+    match vm.rewind.snapshots.pop_back() {
+        Some(snapshot) => {
+            restore_snapshot(vm, snapshot);
+            vm.rewind.frames = 0;
+            true
+        },
+        None => false,
+    }
+}
+
+/// Freeze (or unfreeze) the timers and the GPU.
+///
+/// While frozen, executing an instruction only update the CPU.
+/// This is a debugging tool to isolate the behavior of the CPU:
+/// it breaks the timing of the emulation on purpose.
+pub fn set_peripherals_frozen(vm : &mut Vm, frozen : bool) {
+    vm.peripherals_frozen = frozen;
+}
+
+/// Limit the number of instructions executed by a call to
+/// run_to_vblank (None to remove the limit).
+///
+/// Protect the host from a ROM looping without ever reaching
+/// the VBlank (for example with the LCD or the peripherals frozen).
+pub fn set_instruction_budget(vm : &mut Vm, budget : Option<u64>) {
+    vm.instruction_budget = budget;
+}
+
+/// Choose what happen when an illegal opcode is executed
+///
+/// The real hardware hang (IllegalBehavior::Hang), while the
+/// emulator ignore them by default. A debugger may prefer to stop
+/// on them (IllegalBehavior::Error, see step).
+pub fn set_illegal_opcode_behavior(vm : &mut Vm, behavior : IllegalBehavior) {
+    vm.illegal_opcode_behavior = behavior;
+}
+
+/// Compare the emulated state of two Vm (CPU, timers, memory, GPU
+/// and joypad).
+///
+/// The settings of the host, like callbacks, are ignored. Usefull
+/// to check that two runs are deterministic.
+pub fn state_eq(vm : &Vm, other : &Vm) -> bool {
+    vm.cpu == other.cpu
+        && vm.mmu == other.mmu
+        && vm.gpu == other.gpu
+        && vm.cartridge == other.cartridge
+        && vm.joypad_row_cross == other.joypad_row_cross
+        && vm.joypad_row_buttons == other.joypad_row_buttons
+        && vm.joypad_lines == other.joypad_lines
 }
 
 /// Binary mask associated to the line
@@ -35,36 +351,107 @@ pub mod joypad {
     pub const A      : u8 = 0x01;
 }
 
+#[derive(PartialEq, Eq, Clone, Copy, Default, Debug)]
+/// Keys currently pressed, and columns selected by the game
+pub struct JoypadState {
+    pub down   : bool,
+    pub up     : bool,
+    pub left   : bool,
+    pub right  : bool,
+    pub start  : bool,
+    pub select : bool,
+    pub b      : bool,
+    pub a      : bool,
+    /// Column P14 (cross) selected
+    pub cross_selected   : bool,
+    /// Column P15 (buttons) selected
+    pub buttons_selected : bool,
+}
+
+/// Decode the state of the joypad
+pub fn joypad_state(vm : &Vm) -> JoypadState {
+    let cross = |key : u8| vm.joypad_row_cross & key == 0;
+    let buttons = |key : u8| vm.joypad_row_buttons & key == 0;
+    JoypadState {
+        down   : cross(joypad::DOWN),
+        up     : cross(joypad::UP),
+        left   : cross(joypad::LEFT),
+        right  : cross(joypad::RIGHT),
+        start  : buttons(joypad::START),
+        select : buttons(joypad::SELECT),
+        b      : buttons(joypad::B),
+        a      : buttons(joypad::A),
+        cross_selected   : vm.mmu.joyp & 0x10 == 0,
+        buttons_selected : vm.mmu.joyp & 0x20 == 0,
+    }
+}
+
+/// Value of the lines P10-P13, according to the selected columns.
+///
+/// A line is low (0) when a key of a selected column is pressed.
+pub fn joypad_selected_lines(vm : &Vm) -> u8 {
+    let mut lines = 0x0F;
+    if vm.mmu.joyp & 0x10 == 0 {
+        lines &= vm.joypad_row_cross;
+    }
+    if vm.mmu.joyp & 0x20 == 0 {
+        lines &= vm.joypad_row_buttons;
+    }
+    lines & 0x0F
+}
+
+/// Update the state of the lines P10-P13, and request a joypad
+/// interrupt if one of them goes from high to low.
+/// Such a transition also wake up the CPU from STOP.
+///
+/// Holding a key doesn't trigger the interrupt again.
+pub fn update_joypad_lines(vm : &mut Vm) {
+    let lines = joypad_selected_lines(vm);
+    if vm.joypad_lines & !lines & 0x0F != 0 {
+        raise_interrupt(&mut vm.mmu.ifr, Interrupt::Joypad);
+        vm.cpu.stopped = false;
+    }
+    vm.joypad_lines = lines;
+}
+
 pub fn press_down(vm : &mut Vm) {
     vm.joypad_row_cross &= !joypad::DOWN;
+    update_joypad_lines(vm);
 }
 
 pub fn press_up(vm : &mut Vm) {
     vm.joypad_row_cross &= !joypad::UP;
+    update_joypad_lines(vm);
 }
 
 pub fn press_left(vm : &mut Vm) {
     vm.joypad_row_cross &= !joypad::LEFT;
+    update_joypad_lines(vm);
 }
 
 pub fn press_right(vm : &mut Vm) {
     vm.joypad_row_cross &= !joypad::RIGHT;
+    update_joypad_lines(vm);
 }
 
 pub fn release_down(vm : &mut Vm) {
     vm.joypad_row_cross |= joypad::DOWN;
+    update_joypad_lines(vm);
 }
 
 pub fn release_up(vm : &mut Vm) {
     vm.joypad_row_cross |= joypad::UP;
+    update_joypad_lines(vm);
 }
 
 pub fn release_left(vm : &mut Vm) {
     vm.joypad_row_cross |= joypad::LEFT;
+    update_joypad_lines(vm);
 }
 
 pub fn release_right(vm : &mut Vm) {
     vm.joypad_row_cross |= joypad::RIGHT;
+    update_joypad_lines(vm);
 }
 
 //
@@ -73,32 +460,87 @@ pub fn release_right(vm : &mut Vm) {
 
 pub fn press_start(vm : &mut Vm) {
     vm.joypad_row_buttons &= !joypad::START;
+    update_joypad_lines(vm);
 }
 
 pub fn press_select(vm : &mut Vm) {
     vm.joypad_row_buttons &= !joypad::SELECT;
+    update_joypad_lines(vm);
 }
 
 pub fn press_b(vm : &mut Vm) {
     vm.joypad_row_buttons &= !joypad::B;
+    update_joypad_lines(vm);
 }
 
 pub fn press_a(vm : &mut Vm) {
     vm.joypad_row_buttons &= !joypad::A;
+    update_joypad_lines(vm);
 }
 
 pub fn release_start(vm : &mut Vm) {
     vm.joypad_row_buttons |= joypad::START;
+    update_joypad_lines(vm);
 }
 
 pub fn release_select(vm : &mut Vm) {
     vm.joypad_row_buttons |= joypad::SELECT;
+    update_joypad_lines(vm);
 }
 
 pub fn release_b(vm : &mut Vm) {
     vm.joypad_row_buttons |= joypad::B;
+    update_joypad_lines(vm);
 }
 
 pub fn release_a(vm : &mut Vm) {
     vm.joypad_row_buttons |= joypad::A;
+    update_joypad_lines(vm);
+}
+
+/// Dump the state of the CPU and of the main IO registers as JSON
+/// (only with the `serde_json` feature).
+///
+/// The registers are given as numbers, the interrupt state by its
+/// name, and the IO registers by their name (see IoReg).
+#[cfg(feature = "serde_json")]
+pub fn state_json(vm : &Vm) -> String {
+    use io::*;
+
+    let registers = [IoReg::P1, IoReg::SB, IoReg::SC, IoReg::DIV,
+                     IoReg::TIMA, IoReg::TMA, IoReg::TAC, IoReg::IF,
+                     IoReg::LCDC, IoReg::STAT, IoReg::SCY, IoReg::SCX,
+                     IoReg::LY, IoReg::LYC, IoReg::DMA, IoReg::BGP,
+                     IoReg::OBP0, IoReg::OBP1, IoReg::WY, IoReg::WX,
+                     IoReg::IE];
+    // Read without going through rb, to keep the access log untouched
+    let mut io = ::serde_json::Map::new();
+    for &reg in registers.iter() {
+        let value = dispatch_io_read(io_reg_addr(reg) as usize, vm);
+        io.insert(format!("{:?}", reg), value.into());
+    }
+
+    let r = vm.cpu.registers;
+    let flags = flags(vm);
+    json!({
+        "a" : r.rs[Register::A as usize],
+        "f" : r.rs[Register::F as usize],
+        "b" : r.rs[Register::B as usize],
+        "c" : r.rs[Register::C as usize],
+        "d" : r.rs[Register::D as usize],
+        "e" : r.rs[Register::E as usize],
+        "h" : r.rs[Register::H as usize],
+        "l" : r.rs[Register::L as usize],
+        "sp" : r.sp,
+        "pc" : r.pc,
+        "flags" : {
+            "z" : flags.z,
+            "n" : flags.n,
+            "h" : flags.h,
+            "c" : flags.c,
+        },
+        "cycles" : vm.cpu.clock.t,
+        "interrupt" : format!("{:?}", vm.cpu.interrupt),
+        "io" : io,
+    }).to_string()
 }
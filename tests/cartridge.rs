@@ -0,0 +1,85 @@
+extern crate sgb;
+
+use sgb::*;
+use std::rc::Rc;
+use std::cell::RefCell;
+
+/// Build a 32KB ROM only cartridge, filled with `fill`
+fn make_rom(title : &str, fill : u8) -> Vec<u8> {
+    let mut rom = vec![fill ; 0x8000];
+    for (i, c) in title.bytes().enumerate() {
+        rom[0x134 + i] = c;
+    }
+    rom[0x134 + title.len()] = 0;
+    rom[0x147] = 0x00;
+    rom
+}
+
+#[test]
+fn insert_cartridge_keep_host_settings() {
+    let mut vm : Vm = Default::default();
+    insert_cartridge(&mut vm, make_rom("FIRST", 0x11)).unwrap();
+    assert!(mmu::rb(0x4000, &vm) == 0x11);
+
+    // Register a callback and change the hardware state
+    let calls = Rc::new(RefCell::new(0));
+    let calls_cb = calls.clone();
+    set_scanline_callback(&mut vm, Box::new(move |_, _| {
+        *calls_cb.borrow_mut() += 1;
+    }));
+    mmu::wb(0xC000, 0x42, &mut vm);
+    pc![vm] = 0x1234;
+
+    insert_cartridge(&mut vm, make_rom("SECOND", 0x22)).unwrap();
+
+    // The new rom is mapped and the hardware is reset
+    assert!(mmu::rb(0x4000, &vm) == 0x22);
+    assert!(mmu::rb(0xC000, &vm) == 0x00);
+    assert!(pc![vm] == 0x0000);
+    assert!(vm.mmu.bios_enabled);
+
+    // The callback is still registered
+    assert!(vm.scanline_callback.0.is_some());
+    vm.gpu.mode = GpuMode::VerticalBlank;
+    vm.gpu.line = 152;
+    update_gpu_mode(&mut vm, 456);
+    assert!(*calls.borrow() == 1);
+}
+
+#[test]
+fn insert_cartridge_wrong_size() {
+    let mut vm : Vm = Default::default();
+    insert_cartridge(&mut vm, make_rom("FIRST", 0x11)).unwrap();
+
+    assert!(insert_cartridge(&mut vm, vec![0 ; 0x100]).is_err());
+    assert!(mmu::rb(0x4000, &vm) == 0x11);
+}
+
+#[test]
+fn checksums() {
+    let mut rom = make_rom("TEST", 0x00);
+    rom[0x14D] = 0xA7;
+    rom[0x14E] = 0x01;
+    rom[0x14F] = 0xE7;
+
+    let mut vm : Vm = Default::default();
+    insert_cartridge(&mut vm, rom).unwrap();
+    let report = verify_checksums(&vm);
+    assert!(report.header_computed == 0xA7);
+    assert!(report.global_computed == 0x01E7);
+    assert!(report.header_ok);
+    assert!(report.global_ok);
+
+    // A byte of the code only change the global checksum
+    vm.mmu.srom[0x10] ^= 0x01;
+    let report = verify_checksums(&vm);
+    assert!(report.header_ok);
+    assert!(!report.global_ok);
+
+    // A byte of the header change both
+    vm.mmu.srom[0x10] ^= 0x01;
+    vm.mmu.rom[0x134] ^= 0x01;
+    let report = verify_checksums(&vm);
+    assert!(!report.header_ok);
+    assert!(!report.global_ok);
+}
@@ -0,0 +1,48 @@
+extern crate sgb;
+
+use sgb::*;
+
+#[test]
+fn cfg_loop() {
+    let mut vm : Vm = Default::default();
+    vm.mmu.bios_enabled = false;
+
+    // LD A,5
+    vm.mmu.rom[0x100] = 0x3E;
+    vm.mmu.rom[0x101] = 0x05;
+    // DEC A
+    vm.mmu.rom[0x102] = 0x3D;
+    // JR NZ,-3
+    vm.mmu.rom[0x103] = 0x20;
+    vm.mmu.rom[0x104] = 0xFD;
+    // RET
+    vm.mmu.rom[0x105] = 0xC9;
+    // Data, never reached
+    vm.mmu.rom[0x106] = 0xFF;
+
+    let cfg = build_cfg(&vm, 0x100, 0x200);
+
+    assert!(cfg.blocks == vec![
+        BasicBlock { start : 0x100, end : 0x102, successors : vec![0x102], terminal : false },
+        BasicBlock { start : 0x102, end : 0x105, successors : vec![0x102, 0x105], terminal : false },
+        BasicBlock { start : 0x105, end : 0x106, successors : vec![], terminal : true },
+    ]);
+}
+
+#[test]
+fn cfg_not_logged() {
+    let mut vm : Vm = Default::default();
+    vm.mmu.bios_enabled = false;
+    // JR -2
+    vm.mmu.rom[0x100] = 0x18;
+    vm.mmu.rom[0x101] = 0xFE;
+    enable_access_log(&mut vm, 0x0000..0x8000);
+
+    let cfg = build_cfg(&vm, 0x100, 0x200);
+    assert!(cfg.blocks.len() == 1);
+    assert!(access_log(&vm).is_empty());
+
+    // The same memory read by rb is logged
+    mmu::rb(0x100, &vm);
+    assert!(access_log(&vm).len() == 1);
+}
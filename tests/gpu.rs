@@ -0,0 +1,613 @@
+extern crate sgb;
+
+use sgb::*;
+use std::rc::Rc;
+use std::cell::RefCell;
+
+/// Run the GPU alone during `cycles` cycles, 4 cycles at a time.
+fn run_gpu(vm : &mut Vm, cycles : u64) {
+    for _ in 0..(cycles / 4) {
+        update_gpu_mode(vm, 4);
+    }
+}
+
+#[test]
+fn scanline_callback() {
+    let mut vm : Vm = Default::default();
+
+    let lines = Rc::new(RefCell::new(Vec::new()));
+    let lines_cb = lines.clone();
+    set_scanline_callback(&mut vm, Box::new(move |line, _| {
+        lines_cb.borrow_mut().push(line);
+    }));
+
+    // Start at the last line of vertical blank
+    vm.gpu.mode = GpuMode::VerticalBlank;
+    vm.gpu.line = 152;
+    vm.gpu.clock = 0;
+
+    // Run until the next vertical blank (145 lines of 456 cycles)
+    run_gpu(&mut vm, 145 * 456);
+    assert!(vm.gpu.mode == GpuMode::VerticalBlank);
+
+    let lines = lines.borrow();
+    assert!(lines.len() == 144);
+    for (i, line) in lines.iter().enumerate() {
+        assert!(*line as usize == i);
+    }
+}
+
+#[test]
+fn bg_map() {
+    let mut vm : Vm = Default::default();
+
+    // Tile 1 : every pixels of color 2
+    for i in 0..8 {
+        mmu::wb(0x8010 + i * 2, 0xFF, &mut vm);
+        mmu::wb(0x8010 + i * 2 + 1, 0x00, &mut vm);
+    }
+    // Fill the tile map with tile 1
+    for i in 0..(32 * 32) {
+        mmu::wb(0x9800 + i, 0x01, &mut vm);
+    }
+    // Color 2 is black, all other white
+    vm.gpu.bg_palette = 0x30;
+
+    let map = render_bg_map(&vm, false);
+    assert!(map.len() == 256 * 256 * 3);
+    assert!(map.iter().all(|c| *c == 0x00));
+
+    // The viewport is drawn over the map
+    vm.gpu.scx = 250;
+    vm.gpu.scy = 10;
+    let map = render_bg_map(&vm, true);
+    let pixel = |x : usize, y : usize| {
+        let addr = (y * 256 + x) * 3;
+        (map[addr], map[addr + 1], map[addr + 2])
+    };
+    assert!(pixel(250, 10) == (0xFF, 0x00, 0x00));
+    // Wrapped corner
+    assert!(pixel((250 + 159) % 256, 10 + 143) == (0xFF, 0x00, 0x00));
+    assert!(pixel(0, 0) == (0x00, 0x00, 0x00));
+}
+
+#[test]
+fn tileset_sheet() {
+    let mut vm : Vm = Default::default();
+
+    // Tile 17 : first line of color 3, others of color 0
+    mmu::wb(0x8110, 0xFF, &mut vm);
+    mmu::wb(0x8111, 0xFF, &mut vm);
+    // Tile 300 (0x9000 + 44 * 16) : every pixels of color 1
+    for i in 0..8 {
+        mmu::wb(0x92C0 + i * 2, 0x00, &mut vm);
+        mmu::wb(0x92C0 + i * 2 + 1, 0xFF, &mut vm);
+    }
+    // The palette is ignored
+    vm.gpu.bg_palette = 0x00;
+
+    let sheet = render_tileset(&vm);
+    assert!(sheet.len() == 128 * 192 * 3);
+    let pixel = |x : usize, y : usize| {
+        let addr = (y * 128 + x) * 3;
+        (sheet[addr], sheet[addr + 1], sheet[addr + 2])
+    };
+
+    // Tile 17 is the second tile of the second row
+    assert!(pixel(8, 8) == (0x00, 0x00, 0x00));
+    assert!(pixel(15, 8) == (0x00, 0x00, 0x00));
+    assert!(pixel(8, 9) == (0xFF, 0xFF, 0xFF));
+    assert!(pixel(16, 8) == (0xFF, 0xFF, 0xFF));
+
+    // Tile 300 is the 13th tile of the 19th row
+    assert!(pixel(12 * 8, 18 * 8) == (0xDD, 0xDD, 0xDD));
+    assert!(pixel(12 * 8 + 7, 18 * 8 + 7) == (0xDD, 0xDD, 0xDD));
+    assert!(pixel(12 * 8 - 1, 18 * 8) == (0xFF, 0xFF, 0xFF));
+}
+
+#[test]
+fn bg_display_bit() {
+    let mut vm : Vm = Default::default();
+
+    // Tile 0 : every pixels of color 3, black with the palette
+    for i in 0..16 {
+        mmu::wb(0x8000 + i, 0xFF, &mut vm);
+    }
+    vm.gpu.bg_palette = 0xC0;
+    // Display on, tileset at 0x8000, background on
+    mmu::wb(0xFF40, 0x91, &mut vm);
+    assert!(mmu::rb(0xFF40, &vm) == 0x91);
+
+    vm.gpu.line = 0;
+    render_scanline(&mut vm);
+    assert!(vm.gpu.rendering_memory[0..160 * 3].iter().all(|c| *c == 0x00));
+
+    // Background off : the line is white
+    mmu::wb(0xFF40, 0x90, &mut vm);
+    assert!(mmu::rb(0xFF40, &vm) == 0x90);
+    render_scanline(&mut vm);
+    assert!(vm.gpu.rendering_memory[0..160 * 3].iter().all(|c| *c == 0xFF));
+}
+
+#[test]
+fn lyc_coincidence() {
+    let mut vm : Vm = Default::default();
+    vm.gpu.line = 42;
+    vm.gpu.lyc = 0;
+    vm.gpu.coincidence = false;
+
+    // Enable the LYC=LY interrupt
+    mmu::wb(0xFF41, 0x40, &mut vm);
+    assert!(mmu::rb(0xFF41, &vm) & 0x04 == 0);
+    assert!(!vm.mmu.ifr.lcd_stat);
+
+    // Writing LYC to the current line
+    mmu::wb(0xFF45, 42, &mut vm);
+    assert!(mmu::rb(0xFF41, &vm) & 0x04 != 0);
+    assert!(vm.mmu.ifr.lcd_stat);
+
+    // Writing it again doesn't request a new interrupt
+    vm.mmu.ifr.lcd_stat = false;
+    mmu::wb(0xFF45, 42, &mut vm);
+    assert!(!vm.mmu.ifr.lcd_stat);
+
+    // LY reach LYC
+    mmu::wb(0xFF45, 43, &mut vm);
+    assert!(mmu::rb(0xFF41, &vm) & 0x04 == 0);
+    vm.gpu.mode = GpuMode::HorizontalBlank;
+    vm.gpu.clock = 0;
+    run_gpu(&mut vm, 204);
+    assert!(vm.gpu.line == 43);
+    assert!(mmu::rb(0xFF41, &vm) & 0x04 != 0);
+    assert!(vm.mmu.ifr.lcd_stat);
+}
+
+#[test]
+fn stat_mode_interrupts() {
+    let mut vm : Vm = Default::default();
+    vm.gpu.line = 10;
+    vm.gpu.lyc = 10;
+    vm.gpu.coincidence = true;
+    vm.gpu.mode = GpuMode::ScanlineOAM;
+    vm.gpu.clock = 0;
+
+    // Only the HBlank interrupt is enabled
+    mmu::wb(0xFF41, 0x08, &mut vm);
+    run_gpu(&mut vm, 80);
+    assert!(vm.gpu.mode == GpuMode::ScanlineVRAM);
+    assert!(!vm.mmu.ifr.lcd_stat);
+    while vm.gpu.mode != GpuMode::HorizontalBlank {
+        update_gpu_mode(&mut vm, 4);
+    }
+    assert!(vm.mmu.ifr.lcd_stat);
+
+    // The OAM interrupt, at the next line
+    vm.mmu.ifr.lcd_stat = false;
+    mmu::wb(0xFF41, 0x20, &mut vm);
+    while vm.gpu.mode == GpuMode::HorizontalBlank {
+        update_gpu_mode(&mut vm, 4);
+    }
+    assert!(vm.gpu.mode == GpuMode::ScanlineOAM);
+    assert!(vm.mmu.ifr.lcd_stat);
+
+    // The VBlank interrupt, at the end of the frame
+    vm.mmu.ifr.lcd_stat = false;
+    mmu::wb(0xFF41, 0x10, &mut vm);
+    while vm.gpu.mode != GpuMode::VerticalBlank {
+        assert!(!vm.mmu.ifr.lcd_stat);
+        update_gpu_mode(&mut vm, 4);
+    }
+    assert!(vm.mmu.ifr.lcd_stat);
+}
+
+#[test]
+fn ly_write_reset() {
+    let mut vm : Vm = Default::default();
+    vm.gpu.mode = GpuMode::VerticalBlank;
+    vm.gpu.line = 152;
+    run_gpu(&mut vm, 456 + 10 * 456 + 100);
+    assert!(mmu::rb(0xFF44, &vm) == 10);
+    assert!(mmu::rb(0xFF41, &vm) & 0x04 == 0);
+
+    // The frame restart from line 0, in mode 2
+    mmu::wb(0xFF44, 0x42, &mut vm);
+    assert!(mmu::rb(0xFF44, &vm) == 0);
+    assert!(mmu::rb(0xFF41, &vm) & 0x07 == 0x06);
+    run_gpu(&mut vm, 456);
+    assert!(mmu::rb(0xFF44, &vm) == 1);
+    assert!(mmu::rb(0xFF41, &vm) & 0x07 == 0x02);
+}
+
+#[test]
+fn palettes_colors() {
+    let mut vm : Vm = Default::default();
+    let white = (0xFF, 0xFF, 0xFF);
+    let light = (0xDD, 0xDD, 0xDD);
+    let dark = (0xAA, 0xAA, 0xAA);
+    let black = (0x00, 0x00, 0x00);
+
+    mmu::wb(0xFF47, 0xE4, &mut vm);
+    mmu::wb(0xFF48, 0x1B, &mut vm);
+    mmu::wb(0xFF49, 0xFF, &mut vm);
+
+    assert!(bg_palettes(&vm) == vec![[white, light, dark, black]]);
+    assert!(obj_palettes(&vm) == vec![[black, dark, light, white],
+                                       [black, black, black, black]]);
+}
+
+#[test]
+fn sprite_palettes() {
+    let mut vm : Vm = Default::default();
+    // The OAM can't be written during mode 2
+    vm.gpu.mode = GpuMode::HorizontalBlank;
+
+    // Tile 1 : 4 pixels of color 0, then 4 pixels of color 3
+    for i in 0..16 {
+        mmu::wb(0x8010 + i, 0x0F, &mut vm);
+    }
+    // The background is white
+    vm.gpu.bg_palette = 0x00;
+    // Color 3 is black with OBP0, light grey with OBP1
+    mmu::wb(0xFF48, 0xC0, &mut vm);
+    mmu::wb(0xFF49, 0x40, &mut vm);
+    // Display on, tileset at 0x8000, sprites and background on
+    mmu::wb(0xFF40, 0x93, &mut vm);
+
+    // Sprite 0 at x = 0 with OBP0, sprite 1 at x = 16 with OBP1
+    for &(i, x, attr) in [(0, 8, 0x00), (1, 24, 0x10)].iter() {
+        mmu::wb(0xFE00 + i * 4, 16, &mut vm);
+        mmu::wb(0xFE00 + i * 4 + 1, x, &mut vm);
+        mmu::wb(0xFE00 + i * 4 + 2, 1, &mut vm);
+        mmu::wb(0xFE00 + i * 4 + 3, attr, &mut vm);
+    }
+
+    vm.gpu.line = 0;
+    render_scanline(&mut vm);
+    let pixel = |x : usize| vm.gpu.rendering_memory[x * 3];
+
+    // Color 0 is transparent
+    assert!((0..4).all(|x| pixel(x) == 0xFF));
+    assert!((16..20).all(|x| pixel(x) == 0xFF));
+    // Color 3 use the palette of each sprite
+    assert!((4..8).all(|x| pixel(x) == 0x00));
+    assert!((20..24).all(|x| pixel(x) == 0xDD));
+}
+
+#[test]
+fn sprite_priority_same_x() {
+    let mut vm : Vm = Default::default();
+    // The OAM can't be written during mode 2
+    vm.gpu.mode = GpuMode::HorizontalBlank;
+
+    // Tile 1 : every pixels of color 3
+    for i in 0..16 {
+        mmu::wb(0x8010 + i, 0xFF, &mut vm);
+    }
+    vm.gpu.bg_palette = 0x00;
+    // Color 3 is black with OBP0, light grey with OBP1
+    mmu::wb(0xFF48, 0xC0, &mut vm);
+    mmu::wb(0xFF49, 0x40, &mut vm);
+    mmu::wb(0xFF40, 0x93, &mut vm);
+
+    // Sprite 3 with OBP1 and sprite 5 with OBP0, at the same place
+    for &(i, attr) in [(3, 0x10), (5, 0x00)].iter() {
+        mmu::wb(0xFE00 + i * 4, 16, &mut vm);
+        mmu::wb(0xFE00 + i * 4 + 1, 8, &mut vm);
+        mmu::wb(0xFE00 + i * 4 + 2, 1, &mut vm);
+        mmu::wb(0xFE00 + i * 4 + 3, attr, &mut vm);
+    }
+    vm.gpu.line = 0;
+    assert!(sprites_on_line(&vm) == vec![3, 5]);
+
+    // The lowest OAM index wins
+    render_scanline(&mut vm);
+    assert!((0..8).all(|x| vm.gpu.rendering_memory[x * 3] == 0xDD));
+
+    // Unless the other sprite is on the left
+    mmu::wb(0xFE00 + 5 * 4 + 1, 7, &mut vm);
+    assert!(sprites_on_line(&vm) == vec![5, 3]);
+    render_scanline(&mut vm);
+    assert!((0..7).all(|x| vm.gpu.rendering_memory[x * 3] == 0x00));
+    assert!(vm.gpu.rendering_memory[7 * 3] == 0xDD);
+}
+
+#[test]
+fn window_line_counter() {
+    let mut vm : Vm = Default::default();
+
+    // Tile 1 : color 3 (black), tile 2 : color 2 (dark grey)
+    for i in 0..8 {
+        mmu::wb(0x8010 + i * 2, 0xFF, &mut vm);
+        mmu::wb(0x8010 + i * 2 + 1, 0xFF, &mut vm);
+        mmu::wb(0x8020 + i * 2, 0xFF, &mut vm);
+        mmu::wb(0x8020 + i * 2 + 1, 0x00, &mut vm);
+    }
+    // First row of the window map with tile 1, second with tile 2
+    for i in 0..32 {
+        mmu::wb(0x9C00 + i, 0x01, &mut vm);
+        mmu::wb(0x9C20 + i, 0x02, &mut vm);
+    }
+    vm.gpu.bg_palette = 0xE4;
+    mmu::wb(0xFF4A, 0, &mut vm);
+    mmu::wb(0xFF4B, 7, &mut vm);
+
+    let render_line = |vm : &mut Vm, line : u8, window : bool| {
+        // Display on, window map at 0x9C00, tileset at 0x8000, background on
+        mmu::wb(0xFF40, if window {0xF1} else {0xD1}, vm);
+        vm.gpu.line = line;
+        render_scanline(vm);
+        vm.gpu.rendering_memory[(line as usize) * 160 * 3]
+    };
+
+    for line in 0..4 {
+        assert!(render_line(&mut vm, line, true) == 0x00);
+    }
+    // The window is hidden, the background is white
+    for line in 4..10 {
+        assert!(render_line(&mut vm, line, false) == 0xFF);
+    }
+    // The window resume at its 5th line
+    assert!(render_line(&mut vm, 10, true) == 0x00);
+    assert!(vm.gpu.window_line == 5);
+    for line in 11..14 {
+        assert!(render_line(&mut vm, line, true) == 0x00);
+    }
+    assert!(render_line(&mut vm, 14, true) == 0xAA);
+
+    // The counter is reset for the next frame
+    vm.gpu.mode = GpuMode::HorizontalBlank;
+    vm.gpu.line = 143;
+    vm.gpu.clock = 0;
+    run_gpu(&mut vm, 204);
+    assert!(vm.gpu.mode == GpuMode::VerticalBlank);
+    assert!(vm.gpu.window_line == 0);
+}
+
+#[test]
+fn mode3_length_sprites_scroll() {
+    let mut vm : Vm = Default::default();
+    // The OAM can't be written during mode 2
+    vm.gpu.mode = GpuMode::HorizontalBlank;
+    // Display on, sprites and background on
+    mmu::wb(0xFF40, 0x93, &mut vm);
+    vm.gpu.scx = 3;
+
+    // 3 sprites on the line 0
+    for i in 0..3 {
+        mmu::wb(0xFE00 + i * 4, 16, &mut vm);
+        mmu::wb(0xFE00 + i * 4 + 1, 8 + 20 * i as u8, &mut vm);
+    }
+
+    vm.gpu.mode = GpuMode::ScanlineOAM;
+    vm.gpu.line = 0;
+    vm.gpu.clock = 0;
+    run_gpu(&mut vm, 80);
+    assert!(vm.gpu.mode == GpuMode::ScanlineVRAM);
+    // The sprites at 3 and 43 pixels in the background
+    // start at 3 pixels in a tile: 2 more cycles
+    assert!(vm.gpu.mode3_cycles == 172 + 3 + 3 * 6 + 2 * 2);
+
+    // Still drawing after the minimal 172 cycles
+    run_gpu(&mut vm, 172);
+    assert!(vm.gpu.mode == GpuMode::ScanlineVRAM);
+    run_gpu(&mut vm, 28);
+    assert!(vm.gpu.mode == GpuMode::HorizontalBlank);
+
+    // The HBlank is shorter, the line still last 456 cycles
+    run_gpu(&mut vm, 456 - 80 - 200 - 4);
+    assert!(vm.gpu.line == 0);
+    run_gpu(&mut vm, 4);
+    assert!(vm.gpu.line == 1);
+    assert!(vm.gpu.mode == GpuMode::ScanlineOAM);
+}
+
+#[test]
+fn sprite_penalties() {
+    // Alignment of the sprite with the background tiles
+    assert!(sprite_penalty(0, 0, &mut Vec::new()) == 11);
+    assert!(sprite_penalty(1, 0, &mut Vec::new()) == 10);
+    assert!(sprite_penalty(5, 0, &mut Vec::new()) == 6);
+    assert!(sprite_penalty(7, 0, &mut Vec::new()) == 6);
+    assert!(sprite_penalty(8, 0, &mut Vec::new()) == 11);
+    assert!(sprite_penalty(5, 3, &mut Vec::new()) == 11);
+    assert!(sprite_penalty(5, 4, &mut Vec::new()) == 10);
+    // Fully hidden on the left
+    assert!(sprite_penalty(-8, 0, &mut Vec::new()) == 11);
+    assert!(sprite_penalty(-8, 4, &mut Vec::new()) == 11);
+    assert!(sprite_penalty(-4, 0, &mut Vec::new()) == 7);
+
+    // A tile is only fetched once
+    let mut tiles = Vec::new();
+    assert!(sprite_penalty(16, 0, &mut tiles) == 11);
+    assert!(sprite_penalty(18, 0, &mut tiles) == 6);
+    assert!(sprite_penalty(24, 0, &mut tiles) == 11);
+
+    // Mode 3 of a line with a sprite at OAM X = 9
+    let mut vm : Vm = Default::default();
+    vm.gpu.mode = GpuMode::HorizontalBlank;
+    mmu::wb(0xFF40, 0x93, &mut vm);
+    mmu::wb(0xFE00, 16, &mut vm);
+    mmu::wb(0xFE01, 9, &mut vm);
+    vm.gpu.line = 0;
+    assert!(mode3_length(&vm) == 172 + 10);
+}
+
+#[test]
+fn sprite_y_bounds() {
+    // Lines where a sprite is displayed, by attribute Y
+    let visible_lines = |y : u8, tall : bool| -> Vec<u8> {
+        let mut vm : Vm = Default::default();
+        vm.gpu.mode = GpuMode::HorizontalBlank;
+        let lcdc = lcdc_to_u8(vm.gpu.lcdc);
+        mmu::wb(0xFF40, if tall {lcdc | 0x04} else {lcdc & !0x04}, &mut vm);
+        mmu::wb(0xFE00, y, &mut vm);
+        mmu::wb(0xFE01, 0x10, &mut vm);
+
+        (0..144).filter(|&line| {
+            vm.gpu.line = line;
+            sprites_on_line(&vm) == vec![0]
+        }).collect()
+    };
+
+    // 8x8 sprites
+    assert!(visible_lines(0, false).is_empty());
+    assert!(visible_lines(8, false).is_empty());
+    assert!(visible_lines(9, false) == vec![0]);
+    assert!(visible_lines(16, false) == (0..8).collect::<Vec<u8>>());
+    assert!(visible_lines(151, false) == (135..143).collect::<Vec<u8>>());
+    assert!(visible_lines(159, false) == vec![143]);
+    assert!(visible_lines(160, false).is_empty());
+    assert!(visible_lines(0xFF, false).is_empty());
+
+    // 8x16 sprites
+    assert!(visible_lines(0, true).is_empty());
+    assert!(visible_lines(1, true) == vec![0]);
+    assert!(visible_lines(8, true) == (0..8).collect::<Vec<u8>>());
+    assert!(visible_lines(16, true) == (0..16).collect::<Vec<u8>>());
+    assert!(visible_lines(159, true) == vec![143]);
+    assert!(visible_lines(160, true).is_empty());
+    assert!(visible_lines(0xFF, true).is_empty());
+}
+
+#[test]
+fn dirty_rect_corner() {
+    let mut vm : Vm = Default::default();
+    let next_frame = |vm : &mut Vm| {
+        while vm.gpu.mode == GpuMode::VerticalBlank {
+            update_gpu_mode(vm, 4);
+        }
+        while vm.gpu.mode != GpuMode::VerticalBlank {
+            update_gpu_mode(vm, 4);
+        }
+    };
+
+    // Nothing rendered yet, then a white frame like the initial screen
+    assert!(dirty_rect(&vm) == None);
+    next_frame(&mut vm);
+    assert!(dirty_rect(&vm) == Some(EMPTY_RECT));
+
+    // Tile 1 : first line of color 3, in the bottom right corner
+    mmu::wb(0x8010, 0xFF, &mut vm);
+    mmu::wb(0x8011, 0xFF, &mut vm);
+    mmu::wb(0x9800 + 17 * 32 + 19, 0x01, &mut vm);
+    next_frame(&mut vm);
+    assert!(dirty_rect(&vm) == Some(Rect { x : 152, y : 136, width : 8, height : 1 }));
+
+    // Same frame again
+    next_frame(&mut vm);
+    assert!(dirty_rect(&vm) == Some(EMPTY_RECT));
+
+    // Back to white, and a change in the top left corner
+    mmu::wb(0x9800 + 17 * 32 + 19, 0x00, &mut vm);
+    mmu::wb(0x9800, 0x01, &mut vm);
+    next_frame(&mut vm);
+    assert!(dirty_rect(&vm) == Some(Rect { x : 0, y : 0, width : 160, height : 137 }));
+}
+
+#[test]
+fn tile_data_addressing() {
+    let mut vm : Vm = Default::default();
+    // The OAM can't be written during mode 2
+    vm.gpu.mode = GpuMode::HorizontalBlank;
+
+    // Tile 127 from 0x8000 (0x87F0) : every pixels of color 3
+    for i in 0..16 {
+        mmu::wb(0x87F0 + i, 0xFF, &mut vm);
+    }
+    // Tile 127 from 0x9000 (0x97F0) : every pixels of color 0
+    for i in 0..16 {
+        mmu::wb(0x97F0 + i, 0x00, &mut vm);
+    }
+    // Tile 255 (or -1) is at 0x8FF0 in both modes : color 3
+    for i in 0..16 {
+        mmu::wb(0x8FF0 + i, 0xFF, &mut vm);
+    }
+    // Fill the tile map with tile 127, except the first tile (255)
+    for i in 0..(32 * 32) {
+        mmu::wb(0x9800 + i, 0x7F, &mut vm);
+    }
+    mmu::wb(0x9800, 0xFF, &mut vm);
+    vm.gpu.bg_palette = 0xE4;
+    mmu::wb(0xFF48, 0xE4, &mut vm);
+    vm.gpu.line = 0;
+    let pixel = |vm : &Vm, x : usize| vm.gpu.rendering_memory[x * 3];
+
+    // Display on, tileset at 0x8000, background on
+    mmu::wb(0xFF40, 0x91, &mut vm);
+    render_scanline(&mut vm);
+    assert!((0..160).all(|x| pixel(&vm, x) == 0x00));
+
+    // Tileset at 0x8800 : tile 127 is read at 0x97F0
+    mmu::wb(0xFF40, 0x81, &mut vm);
+    render_scanline(&mut vm);
+    assert!((0..8).all(|x| pixel(&vm, x) == 0x00));
+    assert!((8..160).all(|x| pixel(&vm, x) == 0xFF));
+
+    // Sprites always use the tileset at 0x8000
+    mmu::wb(0xFE00, 16, &mut vm);
+    mmu::wb(0xFE01, 16, &mut vm);
+    mmu::wb(0xFE02, 0x7F, &mut vm);
+    mmu::wb(0xFE03, 0x00, &mut vm);
+    mmu::wb(0xFF40, 0x83, &mut vm);
+    render_scanline(&mut vm);
+    assert!((0..16).all(|x| pixel(&vm, x) == 0x00));
+    assert!((16..160).all(|x| pixel(&vm, x) == 0xFF));
+}
+
+#[cfg(feature = "debug")]
+#[test]
+fn debug_framebuffer() {
+    let mut vm : Vm = Default::default();
+
+    // Horizontal red gradient, vertical green gradient
+    let mut pixels = Vec::new();
+    for y in 0..144 {
+        for x in 0..160 {
+            pixels.push(x as u8);
+            pixels.push(y as u8);
+            pixels.push(0x80);
+        }
+    }
+    debug_set_framebuffer(&mut vm, &pixels);
+    assert!(framebuffer(&vm) == &pixels[..]);
+    assert!(dirty_rect(&vm) == None);
+}
+
+#[test]
+fn oam_dma_during_mode3() {
+    let mut vm : Vm = Default::default();
+
+    // Tile 1 : every pixels of color 3
+    for i in 0..16 {
+        mmu::wb(0x8010 + i, 0xFF, &mut vm);
+    }
+    // The background is white, color 3 is black with OBP0
+    vm.gpu.bg_palette = 0x00;
+    mmu::wb(0xFF48, 0xE4, &mut vm);
+    // Display on, tileset at 0x8000, sprites and background on
+    mmu::wb(0xFF40, 0x93, &mut vm);
+
+    // Sprite 0 at (0, 0) with tile 1, copied by the DMA from 0xC100
+    for (i, &byte) in [16, 8, 1, 0].iter().enumerate() {
+        mmu::wb(0xC100 + i as u16, byte, &mut vm);
+    }
+
+    // Start the DMA during the mode 3 of the first line
+    vm.gpu.line = 0;
+    vm.gpu.clock = 0;
+    vm.gpu.mode = GpuMode::ScanlineVRAM;
+    vm.gpu.mode3_cycles = 172;
+    mmu::wb(0xFF46, 0xC1, &mut vm);
+    run_gpu(&mut vm, 172);
+    assert!(vm.gpu.mode == GpuMode::HorizontalBlank);
+
+    // The GPU couldn't read the OAM : no sprite on this line
+    let pixel = |vm : &Vm, x : usize| vm.gpu.rendering_memory[x * 3];
+    assert!((0..160).all(|x| pixel(&vm, x) == 0xFF));
+
+    // Once the DMA is done, the new sprite is displayed
+    update_dma(&mut vm, DMA_CYCLES);
+    render_scanline(&mut vm);
+    assert!((0..8).all(|x| pixel(&vm, x) == 0x00));
+    assert!((8..160).all(|x| pixel(&vm, x) == 0xFF));
+}
@@ -376,7 +376,8 @@ fn jmphl() {
     reg![vm ; Register::H] = 0x10;
     reg![vm ; Register::L] = 0x33;
 
-    i_jphl(&mut vm);
+    // One byte, 4 cycles
+    assert!(i_jphl(&mut vm) == Clock { m:1, t:4 });
 
     assert!(pc![vm] == 0x1033);
 }
@@ -427,3 +428,1052 @@ fn rrc() {
     assert!(reg![vm ; Register::D] == 0b01111000);
     assert!(flag![vm ; Flag::C] == false);
 }
+
+
+#[test]
+fn rotate_a_clear_z() {
+    let mut vm : Vm = Default::default();
+
+    // RLCA : 0x80 -> 0x01, bit 7 goes in carry
+    reg![vm ; Register::A] = 0x80;
+    set_flag(&mut vm, Flag::Z, true);
+    let clock = i_rlca(&mut vm);
+    assert!(reg![vm ; Register::A] == 0x01);
+    assert!(flag![vm ; Flag::Z] == false);
+    assert!(flag![vm ; Flag::C] == true);
+    assert!(clock == Clock { m:1, t:4 });
+
+    // RRCA : 0x01 -> 0x80, bit 0 goes in carry
+    reg![vm ; Register::A] = 0x01;
+    set_flag(&mut vm, Flag::Z, true);
+    let clock = i_rrca(&mut vm);
+    assert!(reg![vm ; Register::A] == 0x80);
+    assert!(flag![vm ; Flag::Z] == false);
+    assert!(flag![vm ; Flag::C] == true);
+    assert!(clock == Clock { m:1, t:4 });
+
+    // RLA : 0x80 with carry 0 -> 0x00, Z stay cleared
+    reg![vm ; Register::A] = 0x80;
+    set_flag(&mut vm, Flag::C, false);
+    let clock = i_rla(&mut vm);
+    assert!(reg![vm ; Register::A] == 0x00);
+    assert!(flag![vm ; Flag::Z] == false);
+    assert!(flag![vm ; Flag::C] == true);
+    assert!(clock == Clock { m:1, t:4 });
+
+    // RRA : 0x01 with carry 0 -> 0x00, Z stay cleared
+    reg![vm ; Register::A] = 0x01;
+    set_flag(&mut vm, Flag::C, false);
+    let clock = i_rra(&mut vm);
+    assert!(reg![vm ; Register::A] == 0x00);
+    assert!(flag![vm ; Flag::Z] == false);
+    assert!(flag![vm ; Flag::C] == true);
+    assert!(clock == Clock { m:1, t:4 });
+}
+
+
+#[test]
+fn ld_a16_sp() {
+    let mut vm : Vm = Default::default();
+
+    pc![vm] = 0x100;
+    sp![vm] = 0xBEEF;
+
+    // LD (0xC000),SP
+    vm.mmu.rom[0x100] = 0x08;
+    vm.mmu.rom[0x101] = 0x00;
+    vm.mmu.rom[0x102] = 0xC0;
+
+    let t = vm.cpu.clock.t;
+    execute_one_instruction(&mut vm);
+
+    // SP is stored in little endian
+    assert!(mmu::rb(0xC000, &vm) == 0xEF);
+    assert!(mmu::rb(0xC001, &vm) == 0xBE);
+    assert!(pc![vm] == 0x103);
+    assert!(vm.cpu.clock.t - t == 20);
+}
+
+
+#[test]
+fn push_pop_timing() {
+    let mut vm : Vm = Default::default();
+
+    pc![vm] = 0x100;
+    sp![vm] = 0xFFFE;
+
+    reg![vm ; Register::B] = 0x12;
+    reg![vm ; Register::C] = 0x34;
+
+    let push = i_push(&mut vm, Register::B, Register::C);
+    let pop = i_pop(&mut vm, Register::D, Register::E);
+
+    assert!(reg![vm ; Register::D] == 0x12);
+    assert!(reg![vm ; Register::E] == 0x34);
+    assert!(sp![vm] == 0xFFFE);
+    assert!(push == Clock { m:1, t:16 });
+    assert!(pop == Clock { m:1, t:12 });
+}
+
+
+#[test]
+fn run_ten_instructions() {
+    let mut vm : Vm = Default::default();
+
+    pc![vm] = 0x100;
+
+    // LD A,5
+    vm.mmu.rom[0x100] = 0x3E;
+    vm.mmu.rom[0x101] = 0x05;
+    // DEC A
+    vm.mmu.rom[0x102] = 0x3D;
+    // JR NZ,-3
+    vm.mmu.rom[0x103] = 0x20;
+    vm.mmu.rom[0x104] = 0xFD;
+
+    // LD, then 4 * (DEC, JR taken) and a last DEC
+    let cycles = run_instructions(&mut vm, 10);
+
+    assert!(reg![vm ; Register::A] == 0);
+    assert!(pc![vm] == 0x103);
+    assert!(cycles == 8 + 5 * 4 + 4 * 12);
+}
+
+
+#[test]
+fn cb_timing() {
+    let mut vm : Vm = Default::default();
+
+    pc![vm] = 0x100;
+
+    // RLC B
+    vm.mmu.rom[0x100] = 0xCB;
+    vm.mmu.rom[0x101] = 0x00;
+    // RLC (HL)
+    vm.mmu.rom[0x102] = 0xCB;
+    vm.mmu.rom[0x103] = 0x06;
+    // BIT 0,(HL)
+    vm.mmu.rom[0x104] = 0xCB;
+    vm.mmu.rom[0x105] = 0x46;
+
+    reg![vm ; Register::B] = 0x81;
+    reg![vm ; Register::H] = 0xC0;
+    reg![vm ; Register::L] = 0x00;
+    mmu::wb(0xC000, 0x01, &mut vm);
+
+    // The prefix is not counted as an instruction on its own
+    assert!(run_instructions(&mut vm, 1) == 8);
+    assert!(pc![vm] == 0x102);
+    assert!(reg![vm ; Register::B] == 0x03);
+
+    assert!(run_instructions(&mut vm, 1) == 16);
+    assert!(pc![vm] == 0x104);
+    assert!(mmu::rb(0xC000, &vm) == 0x02);
+
+    assert!(run_instructions(&mut vm, 1) == 12);
+    assert!(pc![vm] == 0x106);
+}
+
+
+#[test]
+fn trigger_vblank_interrupt() {
+    let mut vm : Vm = Default::default();
+
+    pc![vm] = 0x150;
+    sp![vm] = 0xFFFE;
+    vm.cpu.interrupt = InterruptState::IEnabled;
+    vm.mmu.ier.vblank = true;
+
+    trigger_interrupt(&mut vm, Interrupt::VBlank);
+
+    assert!(pc![vm] == 0x40);
+    assert!(sp![vm] == 0xFFFC);
+    assert!(mmu::rw(sp![vm], &vm) == 0x150);
+    assert!(vm.mmu.ifr.vblank == false);
+    assert!(vm.cpu.interrupt == InterruptState::IDisabled);
+
+    // Disabled interrupts are only requested
+    trigger_interrupt(&mut vm, Interrupt::Timer);
+    assert!(pc![vm] == 0x40);
+    assert!(vm.mmu.ifr.timer == true);
+}
+
+#[test]
+fn interrupt_callback() {
+    use std::rc::Rc;
+    use std::cell::RefCell;
+
+    let mut vm : Vm = Default::default();
+    vm.mmu.bios_enabled = false;
+    pc![vm] = 0xC000;
+    sp![vm] = 0xFFFE;
+    vm.cpu.interrupt = InterruptState::IEnabled;
+    vm.mmu.ier.timer = true;
+
+    let calls = Rc::new(RefCell::new(Vec::new()));
+    let calls_cb = calls.clone();
+    set_interrupt_callback(&mut vm, Box::new(move |which, return_addr| {
+        calls_cb.borrow_mut().push((which, return_addr));
+    }));
+
+    // TIMA overflow during the NOP at 0xC000 (timer at 4 cycles)
+    mmu::wb(0xFF05, 0xFF, &mut vm);
+    mmu::wb(0xFF07, 0x07, &mut vm);
+    run_instructions(&mut vm, 1);
+
+    assert!(pc![vm] == 0x50);
+    assert!(*calls.borrow() == vec![(Interrupt::Timer, 0xC001)]);
+}
+
+#[test]
+fn call_then_interrupt() {
+    let mut vm : Vm = Default::default();
+    vm.mmu.bios_enabled = false;
+    pc![vm] = 0xC000;
+    sp![vm] = 0xFFFE;
+    vm.cpu.interrupt = InterruptState::IEnabled;
+    vm.mmu.ier.vblank = true;
+    vm.mmu.ifr.vblank = true;
+
+    // CALL 0xC100
+    mmu::wb(0xC000, 0xCD, &mut vm);
+    mmu::wb(0xC001, 0x00, &mut vm);
+    mmu::wb(0xC002, 0xC1, &mut vm);
+
+    assert!(run_instructions(&mut vm, 1) == 24 + 20);
+
+    // The interrupt is serviced at the beginning of the function
+    assert!(pc![vm] == 0x40);
+    assert!(sp![vm] == 0xFFFA);
+    assert!(mmu::rw(0xFFFA, &vm) == 0xC100);
+    assert!(mmu::rw(0xFFFC, &vm) == 0xC003);
+
+    // RETI, then RET go back after the CALL
+    mmu::wb(0xC100, 0xC9, &mut vm);
+    pc![vm] = 0xC200;
+    mmu::wb(0xC200, 0xD9, &mut vm);
+    run_instructions(&mut vm, 1);
+    assert!(pc![vm] == 0xC100);
+    run_instructions(&mut vm, 1);
+    assert!(pc![vm] == 0xC003);
+    assert!(sp![vm] == 0xFFFE);
+}
+
+#[test]
+fn rst_interrupt_timing() {
+    // RST 38h (there is no RST to the interrupt vectors)
+    let mut vm : Vm = Default::default();
+    vm.mmu.bios_enabled = false;
+    pc![vm] = 0xC000;
+    sp![vm] = 0xFFFE;
+    mmu::wb(0xC000, 0xFF, &mut vm);
+
+    assert!(run_instructions(&mut vm, 1) == 16);
+    assert!(pc![vm] == 0x38);
+
+    // VBlank interrupt, same push and jump to 0x40
+    let mut vm : Vm = Default::default();
+    sp![vm] = 0xFFFE;
+    vm.cpu.interrupt = InterruptState::IEnabled;
+    vm.mmu.ier.vblank = true;
+
+    let start = vm.cpu.clock.t;
+    trigger_interrupt(&mut vm, Interrupt::VBlank);
+    assert!(pc![vm] == 0x40);
+    assert!(vm.cpu.clock.t - start == 20);
+}
+
+
+#[test]
+fn inc_dec_hlm_flags() {
+    let mut vm : Vm = Default::default();
+
+    reg![vm ; Register::H] = 0xC0;
+    reg![vm ; Register::L] = 0x00;
+
+    // Half carry
+    mmu::wb(0xC000, 0x0F, &mut vm);
+    set_flag(&mut vm, Flag::C, true);
+    let clock = i_inchlm(&mut vm);
+    assert!(mmu::rb(0xC000, &vm) == 0x10);
+    assert!(flag![vm ; Flag::H] == true);
+    assert!(flag![vm ; Flag::Z] == false);
+    assert!(flag![vm ; Flag::N] == false);
+    assert!(flag![vm ; Flag::C] == true);
+    assert!(clock == Clock { m:1, t:12 });
+
+    // Wrap to zero, carry is preserved
+    mmu::wb(0xC000, 0xFF, &mut vm);
+    set_flag(&mut vm, Flag::C, false);
+    i_inchlm(&mut vm);
+    assert!(mmu::rb(0xC000, &vm) == 0x00);
+    assert!(flag![vm ; Flag::Z] == true);
+    assert!(flag![vm ; Flag::H] == true);
+    assert!(flag![vm ; Flag::C] == false);
+
+    // Decrement back to 0xFF
+    set_flag(&mut vm, Flag::C, true);
+    let clock = i_dechlm(&mut vm);
+    assert!(mmu::rb(0xC000, &vm) == 0xFF);
+    assert!(flag![vm ; Flag::Z] == false);
+    assert!(flag![vm ; Flag::H] == true);
+    assert!(flag![vm ; Flag::N] == true);
+    assert!(flag![vm ; Flag::C] == true);
+    assert!(clock == Clock { m:1, t:12 });
+}
+
+
+#[test]
+fn decode_instructions() {
+    assert!(decode(&[0x00]) == (DecodedInstr::Nop, 1));
+    assert!(decode(&[0x41]) == (DecodedInstr::LdRR { dst : Register::B, src : Register::C }, 1));
+    assert!(decode(&[0x7E]) == (DecodedInstr::LdRHLm { dst : Register::A }, 1));
+    assert!(decode(&[0x3E, 0x42]) == (DecodedInstr::LdRD8 { dst : Register::A, value : 0x42 }, 2));
+    assert!(decode(&[0x21, 0x34, 0x12]) == (DecodedInstr::LdR16D16 { dst : Register16::HL, value : 0x1234 }, 3));
+    assert!(decode(&[0x20, 0xFE]) == (DecodedInstr::JrCc { cc : Condition::NZ, offset : -2 }, 2));
+    assert!(decode(&[0x38, 0x05]) == (DecodedInstr::JrCc { cc : Condition::C, offset : 5 }, 2));
+    assert!(decode(&[0xE6, 0x0F]) == (DecodedInstr::AluImm { op : AluOp::And, value : 0x0F }, 2));
+    assert!(decode(&[0xAE]) == (DecodedInstr::Alu { op : AluOp::Xor, src : Operand8::HLm }, 1));
+    assert!(decode(&[0xCA, 0x00, 0x40]) == (DecodedInstr::JpCc { cc : Condition::Z, addr : 0x4000 }, 3));
+    assert!(decode(&[0xF5]) == (DecodedInstr::Push(Register16::AF), 1));
+    assert!(decode(&[0xFF]) == (DecodedInstr::Rst { addr : 0x38 }, 1));
+    assert!(decode(&[0xE0, 0x40]) == (DecodedInstr::LdMemA { dst : Address::HighImm(0x40) }, 2));
+    assert!(decode(&[0x76]) == (DecodedInstr::Halt, 1));
+    assert!(decode(&[0xD3]) == (DecodedInstr::Invalid(0xD3), 1));
+    assert!(decode(&[0xCB, 0x7C]) == (DecodedInstr::Bit { bit : 7, target : Operand8::Reg(Register::H) }, 2));
+    assert!(decode(&[0xCB, 0x36]) == (DecodedInstr::Shift { op : ShiftOp::Swap, target : Operand8::HLm }, 2));
+}
+
+#[test]
+fn ldi_ldd_keep_flags() {
+    for f in [0x00, 0x50, 0xA0, 0xF0].iter() {
+        let mut vm : Vm = Default::default();
+        reg![vm ; Register::F] = *f;
+
+        // LD (HL+),A
+        reg![vm ; Register::A] = 0x42;
+        reg![vm ; Register::H] = 0xC0;
+        reg![vm ; Register::L] = 0xFF;
+        i_ldihlma(&mut vm);
+        assert!(mmu::rb(0xC0FF, &vm) == 0x42);
+        assert!(hl![vm] == 0xC100);
+        assert!(reg![vm ; Register::F] == *f);
+
+        // LD A,(HL-)
+        reg![vm ; Register::A] = 0x00;
+        reg![vm ; Register::H] = 0xC0;
+        reg![vm ; Register::L] = 0xFF;
+        i_lddahlm(&mut vm);
+        assert!(reg![vm ; Register::A] == 0x42);
+        assert!(hl![vm] == 0xC0FE);
+        assert!(reg![vm ; Register::F] == *f);
+    }
+}
+
+#[test]
+fn registers_by_name() {
+    use std::str::FromStr;
+
+    let mut vm : Vm = Default::default();
+
+    assert!(Register::from_str("A") == Ok(Register::A));
+    assert!(Register::from_str("l") == Ok(Register::L));
+    assert!(Register::from_str("HL").is_err());
+    assert!(Flag::from_str("z") == Ok(Flag::Z));
+    assert!(Flag::from_str("X").is_err());
+
+    set_register_by_name(&mut vm, "A", 42).unwrap();
+    assert!(reg![vm ; Register::A] == 42);
+    assert!(set_register_by_name(&mut vm, "A", 0x100).is_err());
+
+    set_register_by_name(&mut vm, "HL", 0xC123).unwrap();
+    assert!(reg![vm ; Register::H] == 0xC1);
+    assert!(reg![vm ; Register::L] == 0x23);
+    assert!(register_by_name(&vm, "hl") == Ok(0xC123));
+
+    // The lower bits of F don't exist
+    set_register_by_name(&mut vm, "AF", 0x12FF).unwrap();
+    assert!(register_by_name(&vm, "AF") == Ok(0x12F0));
+    set_register_by_name(&mut vm, "F", 0x0F).unwrap();
+    assert!(register_by_name(&vm, "F") == Ok(0x00));
+
+    set_register_by_name(&mut vm, "SP", 0xDFF0).unwrap();
+    set_register_by_name(&mut vm, "PC", 0x0150).unwrap();
+    assert!(sp![vm] == 0xDFF0);
+    assert!(pc![vm] == 0x0150);
+    assert!(register_by_name(&vm, "IX").is_err());
+}
+
+#[test]
+fn add_adc_half_carry() {
+    let mut vm : Vm = Default::default();
+
+    // ADC : 0x0F + 0x00 + carry reach exactly 0x10
+    reg![vm ; Register::A] = 0x0F;
+    reg![vm ; Register::B] = 0x00;
+    set_flag(&mut vm, Flag::C, true);
+    i_adcr(&mut vm, Register::B);
+    assert!(reg![vm ; Register::A] == 0x10);
+    assert!(flag![vm ; Flag::H] == true);
+    assert!(flag![vm ; Flag::C] == false);
+    assert!(flag![vm ; Flag::Z] == false);
+
+    // ADD : 0x08 + 0x08
+    reg![vm ; Register::A] = 0x08;
+    reg![vm ; Register::B] = 0x08;
+    i_addr(&mut vm, Register::B);
+    assert!(reg![vm ; Register::A] == 0x10);
+    assert!(flag![vm ; Flag::H] == true);
+    assert!(flag![vm ; Flag::C] == false);
+
+    // ADD : 0xFF + 0x01 overflow to 0
+    reg![vm ; Register::A] = 0xFF;
+    reg![vm ; Register::B] = 0x01;
+    i_addr(&mut vm, Register::B);
+    assert!(reg![vm ; Register::A] == 0x00);
+    assert!(flag![vm ; Flag::H] == true);
+    assert!(flag![vm ; Flag::C] == true);
+    assert!(flag![vm ; Flag::Z] == true);
+    assert!(flag![vm ; Flag::N] == false);
+}
+
+#[test]
+fn alu_timing() {
+    let mut vm : Vm = Default::default();
+    reg![vm ; Register::H] = 0xC0;
+    reg![vm ; Register::L] = 0x00;
+
+    // Register operand : 4 cycles
+    let register_ops : Vec<fn(&mut Vm, Register) -> Clock> = vec![
+        i_addr, i_adcr, i_subr, i_sbcr, i_andr, i_orr, i_xorr, i_cpr];
+    for op in register_ops {
+        assert!(op(&mut vm, Register::B) == Clock { m:1, t:4 });
+    }
+
+    // (HL) operand : 8 cycles
+    let hlm_ops : Vec<fn(&mut Vm) -> Clock> = vec![
+        i_addhlm, i_adchlm, i_subhlm, i_sbchlm, i_andhlm, i_orhlm, i_xorhlm, i_cphlm];
+    for op in hlm_ops {
+        assert!(op(&mut vm) == Clock { m:1, t:8 });
+    }
+
+    assert!(i_xorr(&mut vm, Register::B) == Clock { m:1, t:4 });
+    assert!(i_xorhlm(&mut vm) == Clock { m:1, t:8 });
+}
+
+#[test]
+fn xor_timing() {
+    let mut vm : Vm = Default::default();
+    vm.mmu.bios_enabled = false;
+    reg![vm ; Register::H] = 0xC0;
+    reg![vm ; Register::L] = 0x00;
+
+    assert!(i_xorr(&mut vm, Register::A) == Clock { m:1, t:4 });
+    assert!(i_xorr(&mut vm, Register::B) == Clock { m:1, t:4 });
+    assert!(i_xorhlm(&mut vm) == Clock { m:1, t:8 });
+    assert!(i_xord8(&mut vm) == Clock { m:2, t:8 });
+}
+
+#[test]
+fn save_restore_registers() {
+    let mut vm : Vm = Default::default();
+    vm.mmu.bios_enabled = false;
+    vm.cpu.registers.pc = 0xC000;
+    // LD BC,0x1234
+    mmu::wb(0xC000, 0x01, &mut vm);
+    mmu::wb(0xC001, 0x34, &mut vm);
+    mmu::wb(0xC002, 0x12, &mut vm);
+
+    let saved = save_registers(&vm);
+    execute_one_instruction(&mut vm);
+    assert!(reg![vm ; Register::B] == 0x12);
+    assert!(save_registers(&vm) != saved);
+
+    restore_registers(&mut vm, saved);
+    assert!(save_registers(&vm) == saved);
+
+    // The lower bits of F can't be set
+    let mut registers = saved;
+    registers.rs[Register::F as usize] = 0xFF;
+    restore_registers(&mut vm, registers);
+    assert!(reg![vm ; Register::F] == 0xF0);
+}
+
+/// Length in bytes of each opcode (0 for the invalid ones),
+/// from the opcode table of the Pan Docs.
+const OPCODE_LENGTHS : [u8 ; 256] = [
+    1, 3, 1, 1, 1, 1, 2, 1, 3, 1, 1, 1, 1, 1, 2, 1, // 0x
+    2, 3, 1, 1, 1, 1, 2, 1, 2, 1, 1, 1, 1, 1, 2, 1, // 1x
+    2, 3, 1, 1, 1, 1, 2, 1, 2, 1, 1, 1, 1, 1, 2, 1, // 2x
+    2, 3, 1, 1, 1, 1, 2, 1, 2, 1, 1, 1, 1, 1, 2, 1, // 3x
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, // 4x
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, // 5x
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, // 6x
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, // 7x
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, // 8x
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, // 9x
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, // Ax
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, // Bx
+    1, 1, 3, 3, 3, 1, 2, 1, 1, 1, 3, 2, 3, 3, 2, 1, // Cx
+    1, 1, 3, 0, 3, 1, 2, 1, 1, 1, 3, 0, 3, 0, 2, 1, // Dx
+    2, 1, 1, 0, 0, 1, 2, 1, 2, 1, 3, 0, 0, 0, 2, 1, // Ex
+    2, 1, 1, 1, 0, 1, 2, 1, 2, 1, 3, 1, 0, 0, 2, 1, // Fx
+];
+
+/// Opcodes that may not continue at the next instruction
+/// (jumps, calls, returns and RST).
+fn is_control_flow(opcode : u8) -> bool {
+    match opcode {
+        0x18 | 0x20 | 0x28 | 0x30 | 0x38 => true,
+        0xC2 | 0xC3 | 0xCA | 0xD2 | 0xDA | 0xE9 => true,
+        0xC4 | 0xCC | 0xCD | 0xD4 | 0xDC => true,
+        0xC0 | 0xC8 | 0xC9 | 0xD0 | 0xD8 | 0xD9 => true,
+        0xC7 | 0xCF | 0xD7 | 0xDF | 0xE7 | 0xEF | 0xF7 | 0xFF => true,
+        _ => false,
+    }
+}
+
+#[test]
+fn opcode_lengths() {
+    let mut mismatches = Vec::new();
+
+    for opcode in 0..256 {
+        let opcode = opcode as u8;
+        let length = OPCODE_LENGTHS[opcode as usize];
+        if length == 0 {
+            continue;
+        }
+
+        let mut vm : Vm = Default::default();
+        vm.mmu.bios_enabled = false;
+        pc![vm] = 0xC000;
+        sp![vm] = 0xDFF0;
+        reg![vm ; Register::H] = 0xC8;
+        reg![vm ; Register::L] = 0x00;
+        mmu::wb(0xC000, opcode, &mut vm);
+
+        execute_one_instruction(&mut vm);
+
+        let m = vm.cpu.clock.m;
+        let pc_delta = pc![vm].wrapping_sub(0xC000);
+        if m != length as u64 {
+            mismatches.push(format!("0x{:02X} : m = {}, expected {}", opcode, m, length));
+        }
+        if !is_control_flow(opcode) && pc_delta != length as u16 {
+            mismatches.push(format!("0x{:02X} : PC + {}, expected {}", opcode, pc_delta, length));
+        }
+    }
+
+    // Prefixed opcodes all use 2 bytes
+    for opcode in 0..256 {
+        let mut vm : Vm = Default::default();
+        vm.mmu.bios_enabled = false;
+        pc![vm] = 0xC000;
+        reg![vm ; Register::H] = 0xC8;
+        reg![vm ; Register::L] = 0x00;
+        mmu::wb(0xC000, 0xCB, &mut vm);
+        mmu::wb(0xC001, opcode as u8, &mut vm);
+
+        execute_one_instruction(&mut vm);
+
+        if vm.cpu.clock.m != 2 || pc![vm] != 0xC002 {
+            mismatches.push(format!("0xCB{:02X} : m = {}, PC + {}", opcode,
+                                    vm.cpu.clock.m, pc![vm].wrapping_sub(0xC000)));
+        }
+    }
+
+    assert!(mismatches.is_empty(), "{}", mismatches.join("\n"));
+}
+
+#[test]
+fn jp_hl_timing() {
+    let mut vm : Vm = Default::default();
+    vm.mmu.bios_enabled = false;
+    pc![vm] = 0xD000;
+    mmu::wb(0xD000, 0xE9, &mut vm);
+    reg![vm ; Register::H] = 0xC0;
+    reg![vm ; Register::L] = 0x00;
+
+    assert!(run_instructions(&mut vm, 1) == 4);
+    assert!(pc![vm] == 0xC000);
+}
+
+#[test]
+fn sp_r8_timing() {
+    // ADD SP,r8
+    let mut vm : Vm = Default::default();
+    vm.mmu.bios_enabled = false;
+    pc![vm] = 0xC000;
+    sp![vm] = 0xDFF0;
+    mmu::wb(0xC000, 0xE8, &mut vm);
+    mmu::wb(0xC001, 0x02, &mut vm);
+
+    assert!(run_instructions(&mut vm, 1) == 16);
+    assert!(pc![vm] == 0xC002);
+    assert!(sp![vm] == 0xDFF2);
+
+    // LD HL,SP+r8
+    let mut vm : Vm = Default::default();
+    vm.mmu.bios_enabled = false;
+    pc![vm] = 0xC000;
+    sp![vm] = 0xDFF0;
+    mmu::wb(0xC000, 0xF8, &mut vm);
+    mmu::wb(0xC001, 0xFE, &mut vm);
+
+    assert!(run_instructions(&mut vm, 1) == 12);
+    assert!(pc![vm] == 0xC002);
+    assert!(hl![vm] == 0xDFEE);
+}
+
+#[test]
+fn res_set_bits() {
+    let registers = [Register::B, Register::C, Register::D, Register::E,
+                     Register::H, Register::L, Register::A, Register::A];
+
+    for opcode in 0x80..0x100 {
+        let opcode = opcode as u8;
+        let set = opcode >= 0xC0;
+        let bit = (opcode >> 3) & 7;
+        let initial = if set {0x00} else {0xFF};
+
+        let mut vm : Vm = Default::default();
+        vm.mmu.bios_enabled = false;
+        pc![vm] = 0xC000;
+        mmu::wb(0xC000, 0xCB, &mut vm);
+        mmu::wb(0xC001, opcode, &mut vm);
+
+        // Operand (HL) is at 0xC800
+        let hlm = opcode & 7 == 6;
+        for r in registers.iter() {
+            reg![vm ; *r] = initial;
+        }
+        if hlm {
+            reg![vm ; Register::H] = 0xC8;
+            reg![vm ; Register::L] = 0x00;
+            mmu::wb(0xC800, initial, &mut vm);
+        }
+
+        execute_one_instruction(&mut vm);
+
+        let value = if hlm {
+            mmu::rb(0xC800, &vm)
+        } else {
+            reg![vm ; registers[(opcode & 7) as usize]]
+        };
+        assert!(value ^ initial == 1 << bit, "opcode 0xCB{:02X}", opcode);
+    }
+}
+
+#[test]
+fn flags_struct() {
+    let expected = Flags { z : true, n : false, h : true, c : true };
+    assert!(u8_to_flags(0xB0) == expected);
+    assert!(u8_to_flags(0xBF) == expected);
+    assert!(flags_to_u8(expected) == 0xB0);
+
+    let mut vm : Vm = Default::default();
+    set_flags(&mut vm, expected);
+    assert!(reg![vm ; Register::F] == 0xB0);
+    assert!(flag![vm ; Flag::H] == true);
+    assert!(flags(&vm) == expected);
+}
+
+#[test]
+fn halt_timer_wake_up() {
+    let mut vm : Vm = Default::default();
+    vm.mmu.bios_enabled = false;
+    pc![vm] = 0xC000;
+    sp![vm] = 0xDFF0;
+    // HALT, then NOP
+    mmu::wb(0xC000, 0x76, &mut vm);
+    mmu::wb(0xC001, 0x00, &mut vm);
+
+    // The timer overflow after 2 increments (16 cycles each)
+    mmu::wb(0xFF05, 0xFE, &mut vm);
+    mmu::wb(0xFF07, 0x04, &mut vm);
+    mmu::wb(0xFFFF, 0x04, &mut vm);
+    vm.cpu.interrupt = InterruptState::IEnabled;
+
+    // The CPU wait, while the clock and the timer are running
+    assert!(run_instructions(&mut vm, 7) == 28);
+    assert!(vm.cpu.halted);
+    assert!(pc![vm] == 0xC001);
+    assert!(vm.cpu.timers.tima == 0xFF);
+
+    // Woken up (4 cycles with IME) and interrupted by the timer
+    assert!(run_instructions(&mut vm, 1) == 4 + 4 + 20);
+    assert!(!vm.cpu.halted);
+    assert!(pc![vm] == 0x50);
+    assert!(mmu::rw(sp![vm], &vm) == 0xC001);
+
+    // Without IME, the execution continue after the HALT
+    let mut vm : Vm = Default::default();
+    vm.mmu.bios_enabled = false;
+    pc![vm] = 0xC000;
+    mmu::wb(0xC000, 0x76, &mut vm);
+    mmu::wb(0xFFFF, 0x01, &mut vm);
+    run_instructions(&mut vm, 10);
+    assert!(vm.cpu.halted);
+    vm.mmu.ifr.vblank = true;
+    run_instructions(&mut vm, 1);
+    assert!(!vm.cpu.halted);
+    assert!(pc![vm] == 0xC001);
+    assert!(vm.mmu.ifr.vblank);
+}
+
+#[test]
+fn halt_wake_cycle() {
+    let mut vm : Vm = Default::default();
+    vm.mmu.bios_enabled = false;
+    pc![vm] = 0xC000;
+    sp![vm] = 0xDFF0;
+    mmu::wb(0xC000, 0x76, &mut vm);
+
+    // TAC = 0x04 (16 cycles per increment), TIMA = 0xFE, TMA = 0xAB:
+    // the overflow happen 32 cycles after the HALT
+    mmu::wb(0xFF05, 0xFE, &mut vm);
+    mmu::wb(0xFF06, 0xAB, &mut vm);
+    mmu::wb(0xFF07, 0x04, &mut vm);
+    mmu::wb(0xFFFF, 0x04, &mut vm);
+    vm.cpu.interrupt = InterruptState::IEnabled;
+
+    // Checked every 4 cycles, the interrupt wake up the CPU at
+    // cycle 32, then 4 cycles of wake up and 20 of dispatch
+    let start = vm.cpu.clock.t;
+    while pc![vm] != 0x50 {
+        execute_one_instruction(&mut vm);
+    }
+    assert!(vm.cpu.clock.t - start == 32 + 4 + 20);
+    // Reloaded from TMA at cycle 32, incremented at cycle 48
+    assert!(vm.cpu.timers.tima == 0xAC);
+    assert!(!vm.mmu.ifr.timer);
+}
+
+#[test]
+fn halt_cycles() {
+    let mut vm : Vm = Default::default();
+    vm.mmu.bios_enabled = false;
+    pc![vm] = 0xC000;
+    mmu::wb(0xC000, 0x76, &mut vm);
+
+    // Without IME, the timer overflow 16 cycles after the HALT
+    // only wake up the CPU
+    mmu::wb(0xFF05, 0xFF, &mut vm);
+    mmu::wb(0xFF07, 0x04, &mut vm);
+    mmu::wb(0xFFFF, 0x04, &mut vm);
+    vm.cpu.interrupt = InterruptState::IDisabled;
+
+    // The clock, DIV and the GPU run while halted
+    let start = vm.cpu.clock.t;
+    let gpu_clock = vm.gpu.clock;
+    let mut steps = 0;
+    execute_one_instruction(&mut vm);
+    while vm.cpu.halted {
+        execute_one_instruction(&mut vm);
+        steps += 1;
+    }
+    assert!(steps == 3);
+    assert!(vm.cpu.clock.t - start == 4 + 3 * 4);
+    assert!(vm.gpu.clock == gpu_clock + 16);
+    assert!(vm.cpu.timers.div == 4);
+    assert!(pc![vm] == 0xC001);
+}
+
+#[test]
+fn interrupt_dispatch_gpu_clock() {
+    let mut vm : Vm = Default::default();
+    vm.mmu.bios_enabled = false;
+    pc![vm] = 0xC000;
+    sp![vm] = 0xDFF0;
+    vm.cpu.interrupt = InterruptState::IEnabled;
+    vm.mmu.ier.timer = true;
+    vm.mmu.ifr.timer = true;
+
+    // The GPU run during the NOP and the dispatch
+    let gpu_clock = vm.gpu.clock;
+    assert!(run_instructions(&mut vm, 1) == 4 + 20);
+    assert!(pc![vm] == 0x50);
+    assert!(vm.gpu.clock == gpu_clock + 4 + 20);
+}
+
+#[test]
+fn halt_deadlock() {
+    let mut vm : Vm = Default::default();
+    vm.mmu.bios_enabled = false;
+    pc![vm] = 0xC000;
+    // HALT with IME and IE cleared, nothing can wake up the CPU
+    mmu::wb(0xC000, 0x76, &mut vm);
+    mmu::wb(0xFFFF, 0x00, &mut vm);
+    vm.cpu.interrupt = InterruptState::IDisabled;
+
+    assert!(run_instructions(&mut vm, 1) == 4);
+    assert!(cpu_locked(&vm));
+    let start = vm.cpu.clock.t;
+    assert!(run_until_serial_pattern(&mut vm, b"Passed", 1000000) == Err(RunError::Locked));
+    assert!(vm.cpu.clock.t - start < 1000);
+}
+
+#[test]
+fn illegal_opcode_behavior() {
+    let illegal_vm = |behavior| {
+        let mut vm : Vm = Default::default();
+        vm.mmu.bios_enabled = false;
+        pc![vm] = 0xC000;
+        mmu::wb(0xC000, 0xD3, &mut vm);
+        set_illegal_opcode_behavior(&mut vm, behavior);
+        vm
+    };
+
+    // Ignored, executed as a NOP
+    let mut vm = illegal_vm(IllegalBehavior::Ignore);
+    assert!(step(&mut vm) == Ok(()));
+    assert!(pc![vm] == 0xC001);
+    assert!(!cpu_locked(&vm));
+
+    // The CPU hang, even when an interrupt is requested
+    let mut vm = illegal_vm(IllegalBehavior::Hang);
+    vm.cpu.interrupt = InterruptState::IEnabled;
+    vm.mmu.ier.vblank = true;
+    assert!(step(&mut vm) == Ok(()));
+    assert!(cpu_locked(&vm));
+    vm.mmu.ifr.vblank = true;
+    assert!(run_instructions(&mut vm, 10) == 40);
+    assert!(pc![vm] == 0xC001);
+    assert!(cpu_locked(&vm));
+    assert!(run_until_serial_pattern(&mut vm, b"Passed", 1000) == Err(RunError::Locked));
+
+    // Reported, and not executed
+    let mut vm = illegal_vm(IllegalBehavior::Error);
+    assert!(step(&mut vm) == Err(IllegalOpcode { pc : 0xC000, opcode : 0xD3 }));
+    assert!(pc![vm] == 0xC000);
+    assert!(run_until_serial_pattern(&mut vm, b"Passed", 1000)
+            == Err(RunError::IllegalOpcode(IllegalOpcode { pc : 0xC000, opcode : 0xD3 })));
+
+    // The runs stop on it, then resume once PC is moved
+    let mut vm = illegal_vm(IllegalBehavior::Error);
+    assert!(run_to_vblank(&mut vm) == Ok(0));
+    assert!(vm.cpu.illegal_opcode == Some(IllegalOpcode { pc : 0xC000, opcode : 0xD3 }));
+    assert!(run_instructions(&mut vm, 10) == 0);
+    assert!(pc![vm] == 0xC000);
+    pc![vm] = 0xC001;
+    assert!(run_instructions(&mut vm, 10) == 40);
+    assert!(vm.cpu.illegal_opcode == None);
+    assert!(run_to_vblank(&mut vm).unwrap() > 0);
+}
+
+#[test]
+fn illegal_opcode_error_pending_interrupt() {
+    let mut vm : Vm = Default::default();
+    vm.mmu.bios_enabled = false;
+    pc![vm] = 0xC000;
+    sp![vm] = 0xDFF0;
+    mmu::wb(0xC000, 0xD3, &mut vm);
+    set_illegal_opcode_behavior(&mut vm, IllegalBehavior::Error);
+    vm.cpu.interrupt = InterruptState::IEnabled;
+    vm.mmu.ier.vblank = true;
+    vm.mmu.ifr.vblank = true;
+
+    // The interrupt isn't serviced, PC stay on the opcode
+    let start = vm.cpu.clock.t;
+    assert!(step(&mut vm) == Err(IllegalOpcode { pc : 0xC000, opcode : 0xD3 }));
+    assert!(pc![vm] == 0xC000);
+    assert!(sp![vm] == 0xDFF0);
+    assert!(vm.mmu.ifr.vblank);
+    assert!(vm.cpu.interrupt == InterruptState::IEnabled);
+    assert!(vm.cpu.clock.t == start);
+}
+
+#[test]
+fn stop_until_joypad() {
+    let mut vm : Vm = Default::default();
+    vm.mmu.bios_enabled = false;
+    pc![vm] = 0xC000;
+    // STOP 0, INC A
+    mmu::wb(0xC000, 0x10, &mut vm);
+    mmu::wb(0xC001, 0x00, &mut vm);
+    mmu::wb(0xC002, 0x3C, &mut vm);
+    reg![vm ; Register::A] = 0;
+    vm.cpu.timers.div = 0x42;
+    // No key pressed, select the cross
+    vm.joypad_row_cross = 0x0F;
+    vm.joypad_row_buttons = 0x0F;
+    mmu::wb(0xFF00, 0x20, &mut vm);
+
+    run_instructions(&mut vm, 1);
+    assert!(vm.cpu.stopped);
+    assert!(pc![vm] == 0xC002);
+    assert!(vm.cpu.timers.div == 0x00);
+
+    // Nothing is executed
+    assert!(run_instructions(&mut vm, 100) == 400);
+    assert!(pc![vm] == 0xC002);
+    assert!(reg![vm ; Register::A] == 0);
+
+    // Until a key is pressed
+    press_down(&mut vm);
+    assert!(!vm.cpu.stopped);
+    run_instructions(&mut vm, 1);
+    assert!(pc![vm] == 0xC003);
+    assert!(reg![vm ; Register::A] == 1);
+}
+
+#[test]
+fn daa_sub_borrow() {
+    // 0x00 - 0x01 = 0x99 in BCD, with a borrow
+    let mut vm : Vm = Default::default();
+    reg![vm ; Register::A] = 0x00;
+    reg![vm ; Register::B] = 0x01;
+    i_subr(&mut vm, Register::B);
+    assert!(flag![vm ; Flag::H] && flag![vm ; Flag::C]);
+    i_daa(&mut vm);
+    assert!(reg![vm ; Register::A] == 0x99);
+    assert!(flag![vm ; Flag::C]);
+    assert!(!flag![vm ; Flag::Z]);
+
+    // A = 0x00 with N, H and C set
+    let mut vm : Vm = Default::default();
+    reg![vm ; Register::A] = 0x00;
+    set_flag(&mut vm, Flag::N, true);
+    set_flag(&mut vm, Flag::H, true);
+    set_flag(&mut vm, Flag::C, true);
+    i_daa(&mut vm);
+    assert!(reg![vm ; Register::A] == 0x9A);
+    assert!(flag![vm ; Flag::C]);
+
+    // 0x99 + 0x01 = 0x00 in BCD, with a carry
+    let mut vm : Vm = Default::default();
+    reg![vm ; Register::A] = 0x99;
+    reg![vm ; Register::B] = 0x01;
+    i_addr(&mut vm, Register::B);
+    i_daa(&mut vm);
+    assert!(reg![vm ; Register::A] == 0x00);
+    assert!(flag![vm ; Flag::Z]);
+    assert!(flag![vm ; Flag::C]);
+}
+
+#[test]
+fn halt_trigger_interrupt() {
+    let mut vm : Vm = Default::default();
+    vm.mmu.bios_enabled = false;
+    pc![vm] = 0xC000;
+    sp![vm] = 0xDFF0;
+    mmu::wb(0xC000, 0x76, &mut vm);
+    // VBlank handler : INC A
+    vm.mmu.rom[0x40] = 0x3C;
+    reg![vm ; Register::A] = 0;
+    vm.cpu.interrupt = InterruptState::IEnabled;
+    vm.mmu.ier.vblank = true;
+
+    run_instructions(&mut vm, 1);
+    assert!(vm.cpu.halted);
+
+    // Woken up, the handler is executed
+    trigger_interrupt(&mut vm, Interrupt::VBlank);
+    assert!(!vm.cpu.halted);
+    assert!(pc![vm] == 0x40);
+    run_instructions(&mut vm, 1);
+    assert!(reg![vm ; Register::A] == 1);
+    assert!(pc![vm] == 0x41);
+
+    // A locked CPU ignore it
+    let mut vm : Vm = Default::default();
+    vm.cpu.locked = true;
+    vm.cpu.interrupt = InterruptState::IEnabled;
+    vm.mmu.ier.vblank = true;
+    pc![vm] = 0xC000;
+    trigger_interrupt(&mut vm, Interrupt::VBlank);
+    assert!(pc![vm] == 0xC000);
+}
+
+#[test]
+fn corrupted_stop() {
+    // STOP, then a non-zero byte (INC A), with `key_held` and `pending`
+    let stop_vm = |key_held : bool, pending : bool| {
+        let mut vm : Vm = Default::default();
+        vm.mmu.bios_enabled = false;
+        mmu::wb(0xC000, 0x10, &mut vm);
+        mmu::wb(0xC001, 0x3C, &mut vm);
+        reg![vm ; Register::A] = 0;
+        vm.cpu.timers.div = 0x42;
+        vm.joypad_row_cross = if key_held {0x0E} else {0x0F};
+        vm.joypad_row_buttons = 0x0F;
+        mmu::wb(0xFF00, 0x20, &mut vm);
+        vm.mmu.ier.timer = true;
+        vm.mmu.ifr.timer = pending;
+        // The opcode was already fetched
+        pc![vm] = 0xC001;
+        vm
+    };
+
+    // Key held, interrupt pending : INC A is executed next
+    let mut vm = stop_vm(true, true);
+    i_stop(&mut vm);
+    assert!(pc![vm] == 0xC001);
+    assert!(!vm.cpu.stopped && !vm.cpu.halted);
+    assert!(vm.cpu.timers.div == 0x42);
+    run_instructions(&mut vm, 1);
+    assert!(reg![vm ; Register::A] == 1);
+
+    // Key held, no interrupt : HALT mode, INC A is skipped
+    let mut vm = stop_vm(true, false);
+    i_stop(&mut vm);
+    assert!(pc![vm] == 0xC002);
+    assert!(vm.cpu.halted && !vm.cpu.stopped);
+    assert!(vm.cpu.timers.div == 0x42);
+
+    // No key held, interrupt pending : STOP mode, INC A is executed
+    // when waking up
+    let mut vm = stop_vm(false, true);
+    i_stop(&mut vm);
+    assert!(pc![vm] == 0xC001);
+    assert!(vm.cpu.stopped);
+    assert!(vm.cpu.timers.div == 0);
+    press_down(&mut vm);
+    vm.mmu.ifr.timer = false;
+    run_instructions(&mut vm, 1);
+    assert!(reg![vm ; Register::A] == 1);
+
+    // No key held, no interrupt : STOP mode, INC A is skipped
+    let mut vm = stop_vm(false, false);
+    i_stop(&mut vm);
+    assert!(pc![vm] == 0xC002);
+    assert!(vm.cpu.stopped);
+    assert!(vm.cpu.timers.div == 0);
+}
+
+#[test]
+fn stop_reset_timers() {
+    let mut vm : Vm = Default::default();
+    vm.mmu.bios_enabled = false;
+    pc![vm] = 0xC000;
+    // NOP, NOP, NOP, STOP 0, NOP
+    for (i, &byte) in [0x00, 0x00, 0x00, 0x10, 0x00, 0x00].iter().enumerate() {
+        mmu::wb(0xC000 + i as u16, byte, &mut vm);
+    }
+    vm.joypad_row_cross = 0x0F;
+    vm.joypad_row_buttons = 0x0F;
+    mmu::wb(0xFF00, 0x20, &mut vm);
+    // Timer running, TIMA incremented every 16 cycles
+    mmu::wb(0xFF07, 0x04, &mut vm);
+
+    run_instructions(&mut vm, 3);
+    assert!(vm.cpu.timers.div != 0);
+    assert!(vm.cpu.timers.imp_nc != 0);
+    let tima = vm.cpu.timers.tima;
+
+    // The counter is reset, and frozen while stopped
+    run_instructions(&mut vm, 1);
+    assert!(vm.cpu.stopped);
+    run_instructions(&mut vm, 100);
+    assert!(vm.cpu.timers.div == 0);
+    assert!(vm.cpu.timers.imp_4c == 0);
+    assert!(vm.cpu.timers.imp_nc == 0);
+    assert!(vm.cpu.timers.tima == tima);
+
+    // Restart from zero when woken up
+    press_down(&mut vm);
+    run_instructions(&mut vm, 1);
+    assert!(pc![vm] == 0xC006);
+    assert!(vm.cpu.timers.div == 1);
+    assert!(vm.cpu.timers.imp_nc == 4);
+}
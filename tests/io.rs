@@ -0,0 +1,231 @@
+extern crate sgb;
+
+use sgb::*;
+
+#[test]
+fn unmapped_io() {
+    let mut vm : Vm = Default::default();
+    let reference : Vm = Default::default();
+
+    let unmapped = [0xFF03, 0xFF08, 0xFF0E, 0xFF15, 0xFF1F, 0xFF27,
+                    0xFF4C, 0xFF4E, 0xFF56, 0xFF7F];
+
+    for &addr in unmapped.iter() {
+        assert!(mmu::rb(addr, &vm) == 0xFF);
+        mmu::wb(addr, 0x00, &mut vm);
+        assert!(mmu::rb(addr, &vm) == 0xFF);
+    }
+
+    // Nothing changed
+    assert!(state_eq(&vm, &reference));
+}
+
+#[test]
+fn cgb_registers_on_dmg() {
+    let mut vm : Vm = Default::default();
+    let reference : Vm = Default::default();
+
+    // KEY1, VBK, HDMA1-5, RP, BCPS, BCPD, OCPS, OCPD, OPRI, SVBK
+    let cgb = [0xFF4D, 0xFF4F, 0xFF51, 0xFF52, 0xFF53, 0xFF54, 0xFF55,
+               0xFF56, 0xFF68, 0xFF69, 0xFF6A, 0xFF6B, 0xFF6C, 0xFF70];
+
+    for &addr in cgb.iter() {
+        assert!(mmu::rb(addr, &vm) == 0xFF);
+        mmu::wb(addr, 0x01, &mut vm);
+        assert!(mmu::rb(addr, &vm) == 0xFF);
+    }
+
+    // Nothing changed (no VRAM or WRAM bank switch, no DMA)
+    assert!(state_eq(&vm, &reference));
+}
+
+#[test]
+fn sound_read_masks() {
+    let mut vm : Vm = Default::default();
+    mmu::wb(0xFF26, 0xFF, &mut vm);
+    assert!(mmu::rb(0xFF26, &vm) == 0xF0);
+
+    // Frequency of channel 1 : only the trigger is lost
+    mmu::wb(0xFF13, 0x34, &mut vm);
+    mmu::wb(0xFF14, 0x87, &mut vm);
+    assert!(mmu::rb(0xFF13, &vm) == 0xFF);
+    assert!(mmu::rb(0xFF14, &vm) == 0xBF);
+    mmu::wb(0xFF14, 0x40, &mut vm);
+    assert!(mmu::rb(0xFF14, &vm) == 0xFF);
+
+    // Sweep and duty
+    mmu::wb(0xFF10, 0x05, &mut vm);
+    assert!(mmu::rb(0xFF10, &vm) == 0x85);
+    mmu::wb(0xFF11, 0x85, &mut vm);
+    assert!(mmu::rb(0xFF11, &vm) == 0xBF);
+    // Volume envelope, fully readable
+    mmu::wb(0xFF12, 0xF3, &mut vm);
+    assert!(mmu::rb(0xFF12, &vm) == 0xF3);
+
+    // Wave pattern RAM
+    mmu::wb(0xFF30, 0x12, &mut vm);
+    assert!(mmu::rb(0xFF30, &vm) == 0x12);
+
+    // Turning the sound off clear the registers
+    mmu::wb(0xFF26, 0x00, &mut vm);
+    assert!(mmu::rb(0xFF26, &vm) == 0x70);
+    assert!(mmu::rb(0xFF12, &vm) == 0x00);
+    mmu::wb(0xFF12, 0xF3, &mut vm);
+    assert!(mmu::rb(0xFF12, &vm) == 0x00);
+    assert!(mmu::rb(0xFF30, &vm) == 0x12);
+}
+
+#[test]
+fn reset_apu_keep_wave_ram() {
+    let mut vm : Vm = Default::default();
+    mmu::wb(0xFF26, 0x80, &mut vm);
+    for addr in 0xFF30..0xFF40 {
+        mmu::wb(addr, addr as u8, &mut vm);
+    }
+    mmu::wb(0xFF1A, 0x80, &mut vm);
+    mmu::wb(0xFF1C, 0x20, &mut vm);
+    mmu::wb(0xFF24, 0x77, &mut vm);
+
+    mmu::wb(0xFF26, 0x00, &mut vm);
+    assert!(mmu::rb(0xFF1A, &vm) == 0x7F);
+    assert!(mmu::rb(0xFF1C, &vm) == 0x9F);
+    assert!(mmu::rb(0xFF24, &vm) == 0x00);
+    for addr in 0xFF30..0xFF40 {
+        assert!(mmu::rb(addr, &vm) == addr as u8);
+    }
+
+    // Same with reset_apu
+    mmu::wb(0xFF26, 0x80, &mut vm);
+    mmu::wb(0xFF24, 0x77, &mut vm);
+    reset_apu(&mut vm);
+    assert!(mmu::rb(0xFF24, &vm) == 0x00);
+    assert!(mmu::rb(0xFF26, &vm) == 0x70);
+    assert!(mmu::rb(0xFF30, &vm) == 0x30);
+}
+
+#[test]
+fn if_unused_bits() {
+    let mut vm : Vm = Default::default();
+
+    mmu::wb(0xFF0F, 0x00, &mut vm);
+    assert!(mmu::rb(0xFF0F, &vm) == 0xE0);
+
+    mmu::wb(0xFF0F, 0xE4, &mut vm);
+    assert!(vm.mmu.ifr.timer);
+    assert!(mmu::rb(0xFF0F, &vm) == 0xE4);
+
+    // Only the 5 interrupts are stored
+    mmu::wb(0xFF0F, 0xFF, &mut vm);
+    assert!(interrupt_to_u8(vm.mmu.ifr) == 0x1F);
+    assert!(mmu::rb(0xFF0F, &vm) == 0xFF);
+}
+
+#[test]
+fn joypad_interrupt_on_press_only() {
+    let mut vm : Vm = Default::default();
+    vm.joypad_row_cross = 0x0F;
+    vm.joypad_row_buttons = 0x0F;
+
+    // Select the buttons column
+    mmu::wb(0xFF00, 0x10, &mut vm);
+    assert!(!vm.mmu.ifr.joypad);
+
+    // Pressing A fires the interrupt
+    press_a(&mut vm);
+    assert!(vm.mmu.ifr.joypad);
+    vm.mmu.ifr.joypad = false;
+
+    // Holding it doesn't
+    press_a(&mut vm);
+    mmu::wb(0xFF00, 0x10, &mut vm);
+    assert!(!vm.mmu.ifr.joypad);
+
+    // Neither releasing it
+    release_a(&mut vm);
+    assert!(!vm.mmu.ifr.joypad);
+
+    // Unselected keys don't fire the interrupt
+    press_down(&mut vm);
+    assert!(!vm.mmu.ifr.joypad);
+}
+
+#[test]
+fn bios_disable_latched() {
+    let mut vm : Vm = Default::default();
+    vm.mmu.bios[0x00] = 0x31;
+    vm.mmu.rom[0x00] = 0xC3;
+    assert!(mmu::rb(0x0000, &vm) == 0x31);
+
+    mmu::wb(0xFF50, 0x01, &mut vm);
+    assert!(mmu::rb(0x0000, &vm) == 0xC3);
+
+    // Writing 0 doesn't map the bios again
+    mmu::wb(0xFF50, 0x00, &mut vm);
+    assert!(!vm.mmu.bios_enabled);
+    assert!(mmu::rb(0x0000, &vm) == 0xC3);
+}
+
+#[test]
+fn io_registers_by_name() {
+    let mut vm : Vm = Default::default();
+
+    write_io(&mut vm, IoReg::BGP, 0xE4);
+    assert!(read_io(&vm, IoReg::BGP) == 0xE4);
+    assert!(vm.gpu.bg_palette == 0xE4);
+
+    // Writing LY reset it
+    vm.gpu.line = 42;
+    write_io(&mut vm, IoReg::LY, 0x12);
+    assert!(read_io(&vm, IoReg::LY) == 0);
+}
+
+#[test]
+fn oam_dma_duration() {
+    let mut vm : Vm = Default::default();
+    vm.mmu.bios_enabled = false;
+    // Run NOPs from the work RAM
+    pc![vm] = 0xC000;
+
+    assert!(!oam_dma_active(&vm));
+    mmu::wb(0xFF46, 0xC1, &mut vm);
+    assert!(oam_dma_active(&vm));
+
+    // The DMA last 640 cycles (160 NOPs)
+    run_instructions(&mut vm, 159);
+    assert!(oam_dma_active(&vm));
+    run_instructions(&mut vm, 1);
+    assert!(!oam_dma_active(&vm));
+}
+
+#[test]
+fn div_write_glitch() {
+    let mut vm : Vm = Default::default();
+    // Timer running, at the period of the mode 00
+    mmu::wb(0xFF07, 0x04, &mut vm);
+    assert!(mmu::rb(0xFF07, &vm) == 0xFC);
+    let period = timer_period(u8_to_tac(0x04));
+
+    // Selected bit set : the reset is a falling edge
+    update_timers(Clock { m:0, t:period / 2 }, &mut vm);
+    assert!(mmu::rb(0xFF05, &vm) == 0x00);
+    mmu::wb(0xFF04, 0x42, &mut vm);
+    assert!(mmu::rb(0xFF05, &vm) == 0x01);
+    assert!(mmu::rb(0xFF04, &vm) == 0x00);
+
+    // The counter was reset with DIV
+    update_timers(Clock { m:0, t:period - 1 }, &mut vm);
+    assert!(mmu::rb(0xFF05, &vm) == 0x01);
+    update_timers(Clock { m:0, t:1 }, &mut vm);
+    assert!(mmu::rb(0xFF05, &vm) == 0x02);
+
+    // Selected bit clear : no increment
+    update_timers(Clock { m:0, t:period / 4 }, &mut vm);
+    mmu::wb(0xFF04, 0x00, &mut vm);
+    assert!(mmu::rb(0xFF05, &vm) == 0x02);
+
+    // Timer stopped : no increment
+    mmu::wb(0xFF07, 0x00, &mut vm);
+    update_timers(Clock { m:0, t:period / 2 }, &mut vm);
+    mmu::wb(0xFF04, 0x00, &mut vm);
+    assert!(mmu::rb(0xFF05, &vm) == 0x02);
+}
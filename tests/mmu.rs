@@ -0,0 +1,222 @@
+extern crate sgb;
+
+use sgb::*;
+use std::ops::{Index, IndexMut};
+
+#[test]
+fn memory_regions_contiguous() {
+    let mut vm : Vm = Default::default();
+
+    for &bios in [true, false].iter() {
+        vm.mmu.bios_enabled = bios;
+        let regions = memory_regions(&vm);
+
+        // Cover the whole memory, without holes
+        assert!(regions[0].start == 0x0000);
+        assert!(regions[regions.len() - 1].end == 0xFFFF);
+        for pair in regions.windows(2) {
+            assert!(pair[0].end as u32 + 1 == pair[1].start as u32);
+        }
+
+        // Switchable bank
+        let srom = regions.iter().find(|r| r.start == 0x4000).unwrap();
+        assert!(srom.end == 0x7FFF);
+        assert!(srom.bank == 1);
+        assert!(!srom.writable);
+    }
+}
+
+#[cfg(feature = "audit")]
+#[test]
+fn audit_address_wrap() {
+    use std::rc::Rc;
+    use std::cell::RefCell;
+
+    let mut vm : Vm = Default::default();
+    vm.mmu.bios_enabled = false;
+
+    let reports = Rc::new(RefCell::new(Vec::new()));
+    let reports_cb = reports.clone();
+    set_audit_callback(&mut vm, Box::new(move |pc, operation| {
+        reports_cb.borrow_mut().push((pc, operation));
+    }));
+
+    // POP BC with SP at the end of the memory
+    vm.cpu.registers.pc = 0xC000;
+    vm.cpu.registers.sp = 0xFFFF;
+    mmu::wb(0xC000, 0xC1, &mut vm);
+    execute_one_instruction(&mut vm);
+
+    assert!(*reports.borrow() == vec![(0xC001, "rw")]);
+}
+
+#[test]
+fn access_log_wram() {
+    let mut vm : Vm = Default::default();
+    vm.mmu.bios_enabled = false;
+    enable_access_log(&mut vm, 0xC000..0xC100);
+
+    // The code is in 0xD000, outside of the logged region
+    pc![vm] = 0xD000;
+    // LD A,0x42
+    mmu::wb(0xD000, 0x3E, &mut vm);
+    mmu::wb(0xD001, 0x42, &mut vm);
+    // LD (0xC010),A
+    mmu::wb(0xD002, 0xEA, &mut vm);
+    mmu::wb(0xD003, 0x10, &mut vm);
+    mmu::wb(0xD004, 0xC0, &mut vm);
+    // LD B,(HL) with HL = 0xC010
+    mmu::wb(0xD005, 0x46, &mut vm);
+    reg![vm ; Register::H] = 0xC0;
+    reg![vm ; Register::L] = 0x10;
+
+    run_instructions(&mut vm, 3);
+    assert!(reg![vm ; Register::B] == 0x42);
+
+    let log = access_log(&vm);
+    assert!(log.len() == 2);
+    assert!(log[0].addr == 0xC010 && log[0].value == 0x42 && log[0].write);
+    assert!(log[0].pc == 0xD002);
+    assert!(log[1].addr == 0xC010 && log[1].value == 0x42 && !log[1].write);
+    assert!(log[1].pc == 0xD005);
+    assert!(log[0].cycle < log[1].cycle);
+}
+
+#[test]
+fn oam_locked_in_mode2() {
+    let mut vm : Vm = Default::default();
+    vm.gpu.mode = GpuMode::HorizontalBlank;
+    mmu::wb(0xFE00, 0x42, &mut vm);
+    assert!(mmu::rb(0xFE00, &vm) == 0x42);
+
+    // Writes are dropped, and reads return 0xFF
+    vm.gpu.mode = GpuMode::ScanlineOAM;
+    mmu::wb(0xFE00, 0x24, &mut vm);
+    assert!(mmu::rb(0xFE00, &vm) == 0xFF);
+    assert!(vm.gpu.sprites[0].y == 0x42 - 16);
+
+    // Normal access in HBlank
+    vm.gpu.mode = GpuMode::HorizontalBlank;
+    assert!(mmu::rb(0xFE00, &vm) == 0x42);
+    mmu::wb(0xFE00, 0x24, &mut vm);
+    assert!(mmu::rb(0xFE00, &vm) == 0x24);
+}
+
+#[test]
+fn echo_ram_mirror() {
+    let mut vm : Vm = Default::default();
+
+    let boundaries = [(0xE000, 0xC000), (0xEFFF, 0xCFFF),
+                      (0xF000, 0xD000), (0xFDFF, 0xDDFF)];
+    for &(echo, wram) in boundaries.iter() {
+        // Write through the echo
+        mmu::wb(echo, 0x5A, &mut vm);
+        assert!(mmu::rb(wram, &vm) == 0x5A);
+
+        // And the other way
+        mmu::wb(wram, 0xA5, &mut vm);
+        assert!(mmu::rb(echo, &vm) == 0xA5);
+    }
+
+    // The echo stop before the OAM
+    mmu::wb(0xDE00, 0x12, &mut vm);
+    assert!(vm.mmu.oam[0] != 0x12);
+}
+
+/// Memory bus backed by an array, recording every access
+struct MockBus {
+    cpu : Cpu,
+    memory : Vec<u8>,
+    reads : std::cell::RefCell<Vec<u16>>,
+    writes : Vec<(u16, u8)>,
+}
+
+fn mock_bus() -> MockBus {
+    MockBus {
+        cpu : Default::default(),
+        memory : vec![0; 0x10000],
+        reads : Default::default(),
+        writes : Vec::new(),
+    }
+}
+
+impl MemoryBus for MockBus {
+    fn read(&self, addr : u16) -> u8 {
+        self.reads.borrow_mut().push(addr);
+        self.memory[addr as usize]
+    }
+
+    fn write(&mut self, addr : u16, value : u8) {
+        self.writes.push((addr, value));
+        self.memory[addr as usize] = value;
+    }
+}
+
+impl Index<CpuIndex> for MockBus {
+    type Output = Cpu;
+
+    fn index(&self, _ : CpuIndex) -> &Cpu {
+        &self.cpu
+    }
+}
+
+impl IndexMut<CpuIndex> for MockBus {
+    fn index_mut(&mut self, _ : CpuIndex) -> &mut Cpu {
+        &mut self.cpu
+    }
+}
+
+impl CpuBus for MockBus {}
+
+#[test]
+fn mock_memory_bus() {
+    let mut bus = mock_bus();
+
+    // JP 0x1234
+    bus.memory[0x0100] = 0xC3;
+    bus.memory[0x0101] = 0x34;
+    bus.memory[0x0102] = 0x12;
+    let (instr, len) = decode_at(&bus, 0x0100);
+    assert!(instr == decode(&[0xC3, 0x34, 0x12]).0);
+    assert!(len == 3);
+    assert!(*bus.reads.borrow() == vec![0x0100, 0x0101, 0x0102]);
+
+    // Words are little endian
+    bus.write_word(0xFFFE, 0xBEEF);
+    assert!(bus.writes == vec![(0xFFFE, 0xEF), (0xFFFF, 0xBE)]);
+    assert!(bus.read_word(0xFFFE) == 0xBEEF);
+
+    // The Vm is a memory bus
+    let mut vm : Vm = Default::default();
+    vm.write_word(0xC000, 0xBEEF);
+    assert!(mmu::rw(0xC000, &vm) == 0xBEEF);
+    assert!(vm.read(0xC001) == 0xBE);
+}
+
+#[test]
+fn instructions_on_mock_bus() {
+    // CALL 0x1234, the opcode was already fetched
+    let mut bus = mock_bus();
+    bus.memory[0x0101] = 0x34;
+    bus.memory[0x0102] = 0x12;
+    pc![bus] = 0x0101;
+    sp![bus] = 0xFFFE;
+
+    i_call(&mut bus);
+    assert!(*bus.reads.borrow() == vec![0x0101, 0x0102]);
+    // The return address is pushed
+    assert!(bus.writes == vec![(0xFFFC, 0x03), (0xFFFD, 0x01)]);
+    assert!(pc![bus] == 0x1234);
+    assert!(sp![bus] == 0xFFFC);
+
+    // LD A,(HL)
+    let mut bus = mock_bus();
+    bus.memory[0xC000] = 0x42;
+    reg![bus ; Register::H] = 0xC0;
+    reg![bus ; Register::L] = 0x00;
+
+    i_ldrr16m(&mut bus, Register::A, Register::H, Register::L);
+    assert!(*bus.reads.borrow() == vec![0xC000]);
+    assert!(bus.writes.is_empty());
+    assert!(reg![bus ; Register::A] == 0x42);
+}
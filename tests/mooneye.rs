@@ -0,0 +1,72 @@
+//! Mooneye GB acceptance tests of the timers
+//!
+//! The ROMs are not distributed with the emulator. Build them from
+//! https://github.com/Gekkio/mooneye-test-suite and copy the `.gb`
+//! files of `acceptance/timer` into `tests/roms/mooneye/timer`, then run
+//! `cargo test --test mooneye -- --ignored`.
+//!
+//! A test ends with `LD B,B` and stores the Fibonacci numbers
+//! 3, 5, 8, 13, 21, 34 in B, C, D, E, H, L when it passes.
+
+extern crate sgb;
+
+use sgb::*;
+use std::path::Path;
+
+/// Emulated time after which a test is considered as stuck
+const MAX_CYCLES : u64 = 4194304 * 20;
+
+/// Run a ROM until it reach `LD B,B`, and tell if it passed
+fn run_mooneye_rom(path : &Path) -> Result<(), String> {
+    let mut vm = match load_rom(path.to_str().unwrap().to_string()) {
+        Ok(vm) => vm,
+        Err(e) => return Err(format!("{}", e)),
+    };
+
+    while mmu::rb(pc![vm], &vm) != 0x40 {
+        if vm.cpu.clock.t > MAX_CYCLES {
+            return Err(format!("timeout at PC = 0x{:04X}", pc![vm]));
+        }
+        execute_one_instruction(&mut vm);
+    }
+
+    let signature = [reg![vm ; Register::B], reg![vm ; Register::C],
+                     reg![vm ; Register::D], reg![vm ; Register::E],
+                     reg![vm ; Register::H], reg![vm ; Register::L]];
+    if signature == [3, 5, 8, 13, 21, 34] {
+        Ok(())
+    } else {
+        Err(format!("registers {:?}", signature))
+    }
+}
+
+#[test]
+#[ignore]
+fn mooneye_timer() {
+    // Name of the test, and what it checks
+    let tests = [
+        ("div_write", "writing DIV reset the whole internal counter"),
+        ("rapid_toggle", "TIMA increment on the falling edge when TAC is toggled"),
+        ("tim00", "TIMA frequency of 4096Hz (TAC = 0b00)"),
+        ("tim00_div_trigger", "TIMA increment when a DIV write cause a falling edge (TAC = 0b00)"),
+        ("tim01", "TIMA frequency of 262144Hz (TAC = 0b01)"),
+        ("tim01_div_trigger", "TIMA increment when a DIV write cause a falling edge (TAC = 0b01)"),
+        ("tim10", "TIMA frequency of 65536Hz (TAC = 0b10)"),
+        ("tim10_div_trigger", "TIMA increment when a DIV write cause a falling edge (TAC = 0b10)"),
+        ("tim11", "TIMA frequency of 16384Hz (TAC = 0b11)"),
+        ("tim11_div_trigger", "TIMA increment when a DIV write cause a falling edge (TAC = 0b11)"),
+        ("tima_reload", "TMA is loaded into TIMA 4 cycles after the overflow"),
+        ("tima_write_reloading", "writing TIMA during the reload delay"),
+        ("tma_write_reloading", "writing TMA during the reload delay"),
+    ];
+
+    let mut failures = Vec::new();
+    for &(name, description) in tests.iter() {
+        let path = format!("tests/roms/mooneye/timer/{}.gb", name);
+        if let Err(error) = run_mooneye_rom(Path::new(&path)) {
+            failures.push(format!("{} ({}) : {}", name, description, error));
+        }
+    }
+
+    assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+}
@@ -0,0 +1,22 @@
+#![cfg(feature = "png")]
+
+extern crate sgb;
+
+use sgb::*;
+use sgb::test_support::*;
+use std::env;
+
+#[test]
+fn frame_png_round_trip() {
+    let mut vm : Vm = Default::default();
+
+    // A gradient of grey
+    for (i, pixel) in vm.gpu.rendering_memory.iter_mut().enumerate() {
+        *pixel = ((i / 3) % 256) as u8;
+    }
+
+    let path = env::temp_dir().join("sgb_frame_png_round_trip.png");
+    save_frame_png(&vm, &path).unwrap();
+    let pixels = load_frame_png(&path).unwrap();
+    assert!(pixels == vm.gpu.rendering_memory);
+}
@@ -0,0 +1,39 @@
+extern crate sgb;
+
+use sgb::*;
+use std::time::Duration;
+
+/// A clock that only move when asked to
+#[derive(Default)]
+struct MockTime {
+    now : Duration,
+    slept : Vec<Duration>,
+}
+
+impl TimeSource for MockTime {
+    fn now(&self) -> Duration { self.now }
+    fn sleep(&mut self, duration : Duration) {
+        self.slept.push(duration);
+        self.now += duration;
+    }
+}
+
+#[test]
+fn pacer_sleep() {
+    let mut pacer : Pacer<MockTime> = Default::default();
+    pacer.time.now = Duration::from_millis(100);
+
+    // Frame shorter than expected : wait the remaining time
+    pacer_frame_start(&mut pacer);
+    pacer.time.now += Duration::from_millis(10);
+    pacer_wait(&mut pacer);
+    assert!(pacer.time.slept == vec![Duration::from_nanos(FRAME_DURATION_NS - 10_000_000)]);
+    assert!(pacer.time.now == Duration::from_millis(100) + Duration::from_nanos(FRAME_DURATION_NS));
+
+    // Frame longer than expected : don't wait
+    pacer_frame_start(&mut pacer);
+    pacer.time.now += Duration::from_millis(20);
+    assert!(pacer_time_to_wait(&pacer) == Duration::from_secs(0));
+    pacer_wait(&mut pacer);
+    assert!(pacer.time.slept.len() == 1);
+}
@@ -0,0 +1,325 @@
+#[macro_use(reg)]
+extern crate sgb;
+#[cfg(feature = "serde_json")]
+extern crate serde_json;
+
+use sgb::*;
+
+/// Create a Vm running a small loop at 0x100
+fn looping_vm() -> Vm {
+    let mut vm : Vm = Default::default();
+
+    pc![vm] = 0x100;
+    // INC A
+    vm.mmu.rom[0x100] = 0x3C;
+    // LD (HL+),A
+    vm.mmu.rom[0x101] = 0x22;
+    // JR -4
+    vm.mmu.rom[0x102] = 0x18;
+    vm.mmu.rom[0x103] = 0xFC;
+    reg![vm ; Register::H] = 0xC0;
+    reg![vm ; Register::L] = 0x00;
+
+    vm
+}
+
+#[test]
+fn state_eq_deterministic() {
+    let mut a = looping_vm();
+    let mut b = looping_vm();
+    set_scanline_callback(&mut b, Box::new(|_, _| ()));
+
+    run_instructions(&mut a, 1000);
+    run_instructions(&mut b, 1000);
+    assert!(state_eq(&a, &b));
+
+    // Diverge
+    run_instructions(&mut b, 1);
+    assert!(!state_eq(&a, &b));
+}
+
+#[test]
+fn peripherals_frozen() {
+    let mut vm = looping_vm();
+    vm.gpu.line = 10;
+    vm.cpu.timers.div = 0x20;
+
+    set_peripherals_frozen(&mut vm, true);
+    run_instructions(&mut vm, 1000);
+
+    assert!(vm.gpu.line == 10);
+    assert!(vm.gpu.clock == 0);
+    assert!(vm.cpu.timers.div == 0x20);
+    assert!(vm.cpu.clock.t > 0);
+    assert!(mmu::rb(0xC000, &vm) == 0x02);
+
+    // Unfreeze
+    set_peripherals_frozen(&mut vm, false);
+    run_instructions(&mut vm, 1000);
+    assert!(vm.cpu.timers.div != 0x20);
+    assert!(vm.gpu.line != 10);
+}
+
+#[test]
+fn peripherals_frozen_trigger_interrupt() {
+    let mut vm = looping_vm();
+    // The timer increments every 16 cycles
+    mmu::wb(0xFF07, 0x04, &mut vm);
+    vm.cpu.interrupt = InterruptState::IEnabled;
+    vm.mmu.ier.vblank = true;
+
+    // Serviced, without running the timers
+    set_peripherals_frozen(&mut vm, true);
+    trigger_interrupt(&mut vm, Interrupt::VBlank);
+    assert!(pc![vm] == 0x40);
+    assert!(vm.cpu.timers.tima == 0);
+}
+
+#[test]
+fn rewind_frames() {
+    let mut vm = looping_vm();
+    enable_rewind(&mut vm, 2, 1);
+
+    // Run 3 frames, and remember after how many instructions
+    // each snapshot was taken
+    let mut snapshots_at = Vec::new();
+    let mut count = 0;
+    while snapshots_at.len() < 3 {
+        let in_vblank = vm.gpu.mode == GpuMode::VerticalBlank;
+        execute_one_instruction(&mut vm);
+        count += 1;
+        if !in_vblank && vm.gpu.mode == GpuMode::VerticalBlank {
+            snapshots_at.push(count);
+        }
+    }
+    run_instructions(&mut vm, 100);
+    assert!(vm.rewind.snapshots.len() == 2);
+
+    // Go back to the last two frames
+    for &n in snapshots_at[1..].iter().rev() {
+        assert!(rewind(&mut vm));
+        let mut expected = looping_vm();
+        run_instructions(&mut expected, n);
+        assert!(state_eq(&vm, &expected));
+    }
+
+    // The first snapshot was dropped
+    assert!(!rewind(&mut vm));
+}
+
+#[test]
+fn joypad_state_pressed() {
+    let mut vm : Vm = Default::default();
+    vm.joypad_row_cross = 0x0F;
+    vm.joypad_row_buttons = 0x0F;
+    mmu::wb(0xFF00, 0x20, &mut vm);
+
+    press_a(&mut vm);
+    press_up(&mut vm);
+
+    let state = joypad_state(&vm);
+    assert!(state == JoypadState {
+        up : true,
+        a : true,
+        cross_selected : true,
+        .. Default::default()
+    });
+}
+
+#[test]
+fn skip_bios_match_bios() {
+    // Run the bios until it jump to the cartridge
+    let mut booted = load_rom("tetris.gb".to_string()).unwrap();
+    while pc![booted] != 0x100 {
+        assert!(booted.cpu.clock.t < 4194304 * 10);
+        execute_one_instruction(&mut booted);
+    }
+
+    let mut skipped = load_rom("tetris.gb".to_string()).unwrap();
+    skip_bios(&mut skipped);
+
+    let registers = [IoReg::TIMA, IoReg::TMA, IoReg::IF, IoReg::LCDC,
+                     IoReg::SCY, IoReg::SCX, IoReg::LYC, IoReg::BGP,
+                     IoReg::WY, IoReg::WX, IoReg::IE];
+    for reg in registers.iter() {
+        assert!(read_io(&booted, *reg) == read_io(&skipped, *reg), "{:?}", reg);
+    }
+    assert!(booted.cpu.registers == skipped.cpu.registers);
+    assert!(booted.cpu.interrupt == skipped.cpu.interrupt);
+    assert!(booted.mmu.bios_enabled == skipped.mmu.bios_enabled);
+    assert!(booted.mmu.sound == skipped.mmu.sound);
+}
+
+#[test]
+fn run_to_vblank_mid_frame() {
+    let mut vm = looping_vm();
+    run_instructions(&mut vm, 1000);
+    assert!(vm.gpu.mode != GpuMode::VerticalBlank);
+
+    let start = vm.cpu.clock.t;
+    let cycles = run_to_vblank(&mut vm).unwrap();
+    assert!(cycles == vm.cpu.clock.t - start);
+    assert!(vm.gpu.mode == GpuMode::VerticalBlank);
+    assert!(vm.gpu.line == 144);
+    // Stopped on the instruction that entered the VBlank
+    assert!(vm.gpu.clock < 12);
+
+    // From VBlank, run whole frames
+    let first = run_to_vblank(&mut vm).unwrap();
+    assert!(vm.gpu.mode == GpuMode::VerticalBlank);
+    assert!(vm.gpu.line == 144);
+    let second = run_to_vblank(&mut vm).unwrap();
+    assert!(first > second - 12 && first < second + 12);
+}
+
+#[test]
+fn instruction_budget() {
+    let mut vm : Vm = Default::default();
+    // JR -2
+    pc![vm] = 0x100;
+    vm.mmu.rom[0x100] = 0x18;
+    vm.mmu.rom[0x101] = 0xFE;
+    // The GPU never reach the VBlank
+    set_peripherals_frozen(&mut vm, true);
+
+    set_instruction_budget(&mut vm, Some(100));
+    assert!(run_to_vblank(&mut vm) == Err(BudgetExceeded { cycles : 100 * 12 }));
+    assert!(pc![vm] == 0x100);
+
+    // Enough budget to reach the VBlank
+    set_peripherals_frozen(&mut vm, false);
+    set_instruction_budget(&mut vm, Some(100000));
+    assert!(run_to_vblank(&mut vm).is_ok());
+    assert!(vm.gpu.mode == GpuMode::VerticalBlank);
+}
+
+#[test]
+fn run_frames_framebuffer() {
+    let mut vm = looping_vm();
+    let frame = run_frames(&mut vm, 3).unwrap().to_vec();
+    assert!(frame.len() == 160 * 144 * 3);
+    assert!(vm.gpu.mode == GpuMode::VerticalBlank);
+
+    // The loop doesn't change the VRAM
+    assert!(run_frames(&mut vm, 2).unwrap() == &frame[..]);
+
+    let mut other = looping_vm();
+    assert!(run_frames(&mut other, 3).unwrap() == &frame[..]);
+}
+
+#[cfg(feature = "serde_json")]
+#[test]
+fn state_json_default() {
+    let vm : Vm = Default::default();
+    let json = state_json(&vm);
+
+    let state : serde_json::Value = serde_json::from_str(&json).unwrap();
+    assert!(state["a"] == 0x01);
+    assert!(state["f"] == 0xB0);
+    assert!(state["h"] == 0x01);
+    assert!(state["l"] == 0x4D);
+    assert!(state["sp"] == 0xFFFE);
+    assert!(state["pc"] == 0x0000);
+    assert!(state["flags"]["z"] == true);
+    assert!(state["flags"]["n"] == false);
+    assert!(state["cycles"] == 0);
+    assert!(state["interrupt"] == "IDisabled");
+    assert!(state["io"]["LCDC"] == 0x91);
+    assert!(state["io"]["TAC"] == 0xF8);
+}
+
+#[test]
+fn trace_ring() {
+    let mut vm = looping_vm();
+    enable_trace_ring(&mut vm, 4);
+
+    run_instructions(&mut vm, 2);
+    let trace = recent_trace(&vm);
+    assert!(trace.len() == 2);
+    assert!(trace[0].pc == 0x100 && trace[0].opcode == 0x3C);
+    assert!(trace[1].pc == 0x101 && trace[1].opcode == 0x22);
+    // Registers before the instruction
+    assert!(trace[1].registers.rs[Register::A as usize] == trace[0].registers.rs[Register::A as usize] + 1);
+
+    // Only the last 4 are kept
+    run_instructions(&mut vm, 1001);
+    let trace = recent_trace(&vm);
+    let pcs : Vec<u16> = trace.iter().map(|entry| entry.pc).collect();
+    assert!(pcs == vec![0x100, 0x101, 0x102, 0x100]);
+    assert!(trace[3].registers.pc == 0x100);
+}
+
+#[test]
+fn executed_addresses() {
+    let mut vm = looping_vm();
+    // Data after the loop
+    vm.mmu.rom[0x104] = 0x76;
+
+    run_instructions(&mut vm, 10);
+    assert!(executed_map(&vm).is_empty());
+
+    enable_executed_map(&mut vm);
+    run_instructions(&mut vm, 10);
+    assert!(executed_map(&vm).len() == 0x10000 / 64);
+    let executed : Vec<usize> = (0..0x10000).filter(|&addr| was_executed(&vm, addr as u16)).collect();
+    // The operand of JR is not an instruction
+    assert!(executed == vec![0x100, 0x101, 0x102]);
+
+    disable_executed_map(&mut vm);
+    assert!(executed_map(&vm).is_empty());
+    assert!(!was_executed(&vm, 0x100));
+}
+
+#[test]
+fn serial_pattern() {
+    let mut vm : Vm = Default::default();
+    pc![vm] = 0x100;
+    let program = [
+        0x21, 0x10, 0x01, // LD HL,0x0110
+        0x2A,             // LD A,(HL+)
+        0xE0, 0x01,       // LDH (SB),A
+        0x3E, 0x81,       // LD A,0x81
+        0xE0, 0x02,       // LDH (SC),A
+        0x18, 0xF7,       // JR -9
+    ];
+    vm.mmu.rom[0x100..0x100 + program.len()].copy_from_slice(&program);
+    vm.mmu.rom[0x110..0x116].copy_from_slice(b"Passed");
+
+    assert!(run_until_serial_pattern(&mut vm, b"Passed", 10000) == Ok(()));
+    assert!(vm.serial_output == b"Passed".to_vec());
+    // Transfer done, nothing received
+    assert!(mmu::rb(0xFF01, &vm) == 0xFF);
+    assert!(mmu::rb(0xFF02, &vm) == 0x7F);
+    assert!(vm.mmu.ifr.serial);
+
+    assert!(run_until_serial_pattern(&mut vm, b"Failed", 10000)
+            == Err(RunError::Timeout));
+    // The bytes sent before the call are ignored
+    assert!(run_until_serial_pattern(&mut vm, b"Passed", 10000)
+            == Err(RunError::Timeout));
+    assert!(run_until_serial_pattern(&mut vm, b"", 10) == Ok(()));
+}
+
+#[test]
+fn present_interval() {
+    use std::rc::Rc;
+    use std::cell::Cell;
+
+    let mut vm = looping_vm();
+    let presented = Rc::new(Cell::new(0));
+    let presented_cb = presented.clone();
+    set_frame_callback(&mut vm, Box::new(move |frame| {
+        assert!(frame.len() == 160 * 144 * 3);
+        presented_cb.set(presented_cb.get() + 1);
+    }));
+
+    run_frames(&mut vm, 10).unwrap();
+    assert!(vm.frame_count == 10);
+    assert!(presented.get() == 10);
+
+    // Present one frame out of two
+    set_present_interval(&mut vm, 2);
+    run_frames(&mut vm, 10).unwrap();
+    assert!(vm.frame_count == 20);
+    assert!(presented.get() == 15);
+}